@@ -0,0 +1,478 @@
+//! # Annotate driver.
+//! <p> Usage: <em> ruperf annotate [OPTION] [SYMBOL] </em>
+//! Disassembles a sampled function with `objdump` and shows
+//! the share of samples that hit each instruction, with source
+//! lines interleaved when the binary has DWARF line info. </p>
+
+extern crate structopt;
+use crate::record::data::*;
+use crate::report::index_records;
+use crate::symbols::Location;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use structopt::StructOpt;
+
+/// Instructions, loops and blocks at or above this
+/// share of the function's samples are highlighted.
+const HOT_PERCENT: f64 = 5.0;
+
+/// Instructions above this share are colored as warm.
+const WARM_PERCENT: f64 = 0.5;
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Configuration settings for running annotate. See `./ruperf annotate --help`
+/// for more information.
+#[derive(Debug, StructOpt)]
+pub struct AnnotateOptions {
+    #[structopt(
+        short,
+        long,
+        help = "Data file to read",
+        default_value = "ruperf.data",
+        parse(from_os_str)
+    )]
+    pub input: PathBuf,
+
+    #[structopt(
+        long,
+        help = "Disassembler to run, e.g. aarch64-linux-gnu-objdump",
+        default_value = "objdump"
+    )]
+    pub objdump: String,
+
+    #[structopt(long = "no-source", help = "Don't interleave source lines")]
+    pub no_source: bool,
+
+    #[structopt(help = "Function to annotate, the hottest one if not given")]
+    pub symbol: Option<String>,
+}
+
+/// One line of `objdump -d -l` output.
+#[derive(Debug, PartialEq, Eq)]
+enum AsmLine {
+    /// Start of the code generated for a source line.
+    Source { file: String, line: usize },
+    Insn {
+        addr: u64,
+        text: String,
+        /// Destination of a direct branch.
+        target: Option<u64>,
+    },
+}
+
+/// Sampled addresses of one function for one event.
+#[derive(Default)]
+struct Hits {
+    period: u64,
+    samples: u64,
+    by_addr: HashMap<u64, u64>,
+}
+
+/// A sampled function.
+struct Function {
+    location: Location,
+    /// Hits per event index.
+    hits: HashMap<usize, Hits>,
+}
+
+impl Function {
+    fn period(&self) -> u64 {
+        self.hits.values().map(|h| h.period).sum()
+    }
+}
+
+/// True if `name` is `wanted`, or `wanted` with a module path.
+fn matches_symbol(name: &str, wanted: &str) -> bool {
+    name == wanted
+        || name
+            .strip_suffix(wanted)
+            .is_some_and(|prefix| prefix.ends_with("::"))
+}
+
+/// Destination of a direct jump or conditional branch, for
+/// both x86_64 (`jne 4011a0 <f+0x10>`) and aarch64
+/// (`b.ne 4005a0 <f+0x10>`, `cbz w0, 4005a0 <f+0x20>`).
+/// Calls aren't branches within the function.
+fn branch_target(text: &str) -> Option<u64> {
+    let mnemonic = text.split_whitespace().next()?;
+    let branch = mnemonic.starts_with('j')
+        || mnemonic == "b"
+        || mnemonic.starts_with("b.")
+        || matches!(mnemonic, "cbz" | "cbnz" | "tbz" | "tbnz");
+    if !branch {
+        return None;
+    }
+    let (operands, _) = text.split_once(" <")?;
+    let hex = operands.rsplit([' ', '\t', ',']).next()?;
+    u64::from_str_radix(hex.trim_start_matches("0x"), 16).ok()
+}
+
+/// Parse the output of `objdump -d -l --no-show-raw-insn`.
+fn parse_objdump(output: &str) -> Vec<AsmLine> {
+    let mut lines = Vec::new();
+    for line in output.lines() {
+        if let Some((addr, text)) = line.split_once(":\t") {
+            if let Ok(addr) = u64::from_str_radix(addr.trim(), 16) {
+                let text = text.trim().to_string();
+                if text.is_empty() || text == "..." {
+                    continue;
+                }
+                lines.push(AsmLine::Insn {
+                    addr,
+                    target: branch_target(&text),
+                    text,
+                });
+                continue;
+            }
+        }
+        // `-l` marks source lines as `path:line`,
+        // sometimes followed by a discriminator.
+        if line.starts_with(char::is_whitespace) || line.ends_with("():") {
+            continue;
+        }
+        let location = line.split(" (discriminator").next().unwrap_or(line);
+        if let Some((file, number)) = location.rsplit_once(':') {
+            if let Ok(number) = number.parse() {
+                lines.push(AsmLine::Source {
+                    file: file.to_string(),
+                    line: number,
+                });
+            }
+        }
+    }
+    lines
+}
+
+/// Split the instructions `[start, end)` into basic blocks,
+/// returning the first address of each block. Blocks start at
+/// the function's entry, at branch targets inside it, and
+/// after every branch.
+fn block_starts(lines: &[AsmLine], start: u64, end: u64) -> BTreeSet<u64> {
+    let mut starts = BTreeSet::new();
+    starts.insert(start);
+    let mut after_branch = false;
+    for line in lines {
+        if let AsmLine::Insn { addr, target, .. } = line {
+            if after_branch {
+                starts.insert(*addr);
+            }
+            after_branch = target.is_some();
+            if let Some(t) = target {
+                if (start..end).contains(t) {
+                    starts.insert(*t);
+                }
+            }
+        }
+    }
+    starts
+}
+
+/// Share of `hits` between `from` and `to` inclusive.
+fn range_percent(hits: &Hits, from: u64, to: u64) -> f64 {
+    let period: u64 = hits
+        .by_addr
+        .iter()
+        .filter(|(a, _)| (from..=to).contains(*a))
+        .map(|(_, p)| p)
+        .sum();
+    period as f64 * 100.0 / hits.period.max(1) as f64
+}
+
+/// Source of `file`, split into lines, read on first use.
+fn source_lines<'a>(
+    cache: &'a mut HashMap<String, Option<Vec<String>>>,
+    file: &str,
+) -> Option<&'a Vec<String>> {
+    cache
+        .entry(file.to_string())
+        .or_insert_with(|| {
+            fs::read_to_string(file)
+                .ok()
+                .map(|s| s.lines().map(|l| l.to_string()).collect())
+        })
+        .as_ref()
+}
+
+/// Print the annotated disassembly of `function` for one event.
+fn print_annotation(
+    event: &str,
+    function: &Function,
+    hits: &Hits,
+    lines: &[AsmLine],
+    end: u64,
+    options: &AnnotateOptions,
+) {
+    let color = unsafe { libc::isatty(libc::STDOUT_FILENO) } == 1;
+    let paint = |code: &str, text: String| {
+        if color {
+            format!("{}{}{}", code, text, RESET)
+        } else {
+            text
+        }
+    };
+    let loc = &function.location;
+    let start = loc.symbol_start;
+    let percent = |period: u64| period as f64 * 100.0 / hits.period.max(1) as f64;
+
+    // The hottest basic block is marked in the gutter.
+    let starts = block_starts(lines, start, end);
+    let insns: Vec<u64> = lines
+        .iter()
+        .filter_map(|l| match l {
+            AsmLine::Insn { addr, .. } => Some(*addr),
+            _ => None,
+        })
+        .collect();
+    let block_end = |first: u64| {
+        let next = starts.range(first + 1..).next().copied().unwrap_or(end);
+        insns
+            .iter()
+            .rev()
+            .copied()
+            .find(|a| *a < next)
+            .unwrap_or(first)
+    };
+    let hottest = starts
+        .iter()
+        .map(|s| (*s, block_end(*s), range_percent(hits, *s, block_end(*s))))
+        .max_by(|a, b| a.2.partial_cmp(&b.2).unwrap());
+
+    println!(
+        " Percent |  Source code & Disassembly of {} for {} ({} samples)",
+        loc.dso_name(),
+        event,
+        hits.samples
+    );
+    println!("{}", "-".repeat(72));
+    println!("         :  {:x} <{}>:", start, loc.symbol_name());
+
+    let mut sources = HashMap::new();
+    let mut last_source = None;
+    for line in lines {
+        match line {
+            AsmLine::Source { file, line } => {
+                if options.no_source || last_source == Some((file, *line)) {
+                    continue;
+                }
+                last_source = Some((file, *line));
+                let name = Path::new(file)
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or(file);
+                let text = source_lines(&mut sources, file)
+                    .and_then(|s| s.get(line.wrapping_sub(1)))
+                    .map(|s| s.trim())
+                    .unwrap_or("");
+                println!("         :  {}:{}  {}", name, line, text);
+            }
+            AsmLine::Insn { addr, text, target } => {
+                let p = percent(hits.by_addr.get(addr).copied().unwrap_or(0));
+                let cell = format!("{:>8.2}", p);
+                let cell = if p >= HOT_PERCENT {
+                    paint(RED, cell)
+                } else if p > WARM_PERCENT {
+                    paint(GREEN, cell)
+                } else {
+                    cell
+                };
+                let gutter = match hottest {
+                    Some((from, to, share)) if share > 0.0 && (from..=to).contains(addr) => '▌',
+                    _ => ' ',
+                };
+                let mut text = text.clone();
+                if let Some(t) = target {
+                    if (start..end).contains(t) && t <= addr {
+                        // A backward branch closes a loop over [t, addr].
+                        let share = range_percent(hits, *t, *addr);
+                        text += &if share >= HOT_PERCENT {
+                            paint(BOLD, format!("  ↑ loop {:.2}%", share))
+                        } else if share > 0.0 {
+                            format!("  ↑ loop {:.2}%", share)
+                        } else {
+                            "  ↑".to_string()
+                        };
+                    } else if (start..end).contains(t) {
+                        text += "  ↓";
+                    }
+                }
+                println!("{} :{}{:>8x}:  {}", cell, gutter, addr, text);
+            }
+        }
+    }
+    if let Some((from, to, share)) = hottest {
+        if share > 0.0 {
+            println!();
+            println!(
+                "# Hottest block: {:x}-{:x} ({:.2}% of samples)",
+                from, to, share
+            );
+        }
+    }
+    println!();
+}
+
+/// Disassemble `[start, end)` of the file at `path`.
+fn disassemble(objdump: &str, path: &str, start: u64, end: u64) -> Result<String, String> {
+    let output = Command::new(objdump)
+        .args([
+            "-d",
+            "-l",
+            "-C",
+            "--no-show-raw-insn",
+            &format!("--start-address={:#x}", start),
+            &format!("--stop-address={:#x}", end),
+            path,
+        ])
+        .output()
+        .map_err(|e| format!("failed to run {}: {}", objdump, e))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Run perf annotate on a data file.
+pub fn run_annotate(options: AnnotateOptions) {
+    let (header, records) = match read_data(&options.input) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("ruperf annotate: {}: {}", options.input.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let (mut symbolizer, _) = index_records(&records);
+
+    // Group samples by the function they landed in.
+    let mut functions: HashMap<(String, u64), Function> = HashMap::new();
+    for record in &records {
+        if let DataRecord::Sample {
+            event,
+            ip,
+            pid,
+            period,
+            kernel,
+            ..
+        } = record
+        {
+            let loc = symbolizer.resolve(*pid, *ip, *kernel);
+            let name = match &loc.symbol {
+                Some(name) => name,
+                None => continue,
+            };
+            if let Some(wanted) = &options.symbol {
+                if !matches_symbol(name, wanted) {
+                    continue;
+                }
+            }
+            let addr = loc.addr;
+            let function = functions
+                .entry((loc.dso.clone(), loc.symbol_start))
+                .or_insert_with(|| Function {
+                    location: loc,
+                    hits: HashMap::new(),
+                });
+            let hits = function.hits.entry(*event).or_default();
+            hits.period += period;
+            hits.samples += 1;
+            *hits.by_addr.entry(addr).or_insert(0) += period;
+        }
+    }
+
+    let mut functions: Vec<Function> = functions.into_values().collect();
+    functions.sort_by_key(|f| std::cmp::Reverse(f.period()));
+    if options.symbol.is_none() {
+        functions.truncate(1);
+    }
+    if functions.is_empty() {
+        match &options.symbol {
+            Some(s) => eprintln!("ruperf annotate: no samples in '{}'", s),
+            None => eprintln!("ruperf annotate: no samples with symbols"),
+        }
+        std::process::exit(1);
+    }
+
+    for function in &functions {
+        let loc = &function.location;
+        if loc.kernel {
+            eprintln!(
+                "ruperf annotate: can't disassemble kernel function {}",
+                loc.symbol_name()
+            );
+            continue;
+        }
+        let start = loc.symbol_start;
+        let end = match symbolizer.table(&loc.dso).and_then(|t| t.end_of(start)) {
+            Some(end) => end,
+            None => {
+                eprintln!(
+                    "ruperf annotate: {}: can't find the end of {}",
+                    loc.dso,
+                    loc.symbol_name()
+                );
+                continue;
+            }
+        };
+        let lines = match disassemble(&options.objdump, &loc.dso, start, end) {
+            Ok(out) => parse_objdump(&out),
+            Err(e) => {
+                eprintln!("ruperf annotate: {}: {}", loc.dso, e);
+                continue;
+            }
+        };
+        let mut events: Vec<(&usize, &Hits)> = function.hits.iter().collect();
+        events.sort_by_key(|(e, _)| **e);
+        for (event, hits) in events {
+            let name = header.events.get(*event).map_or("?", |s| s.as_str());
+            print_annotation(name, function, hits, &lines, end, &options);
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn parse_objdump_test() {
+    let x86 = "\
+0000000000001130 <spin>:
+spin():
+/src/spin.c:3
+    1130:\tmov    $0x0,%eax
+/src/spin.c:4 (discriminator 1)
+    1135:\tadd    $0x1,%eax
+    1138:\tcmp    $0x64,%eax
+    113b:\tjne    1135 <spin+0x5>
+    113d:\tret
+";
+    let lines = parse_objdump(x86);
+    assert_eq!(
+        lines[0],
+        AsmLine::Source {
+            file: "/src/spin.c".to_string(),
+            line: 3
+        }
+    );
+    assert_eq!(lines.len(), 7);
+    assert_eq!(
+        lines[5],
+        AsmLine::Insn {
+            addr: 0x113b,
+            text: "jne    1135 <spin+0x5>".to_string(),
+            target: Some(0x1135)
+        }
+    );
+    let starts: Vec<u64> = block_starts(&lines, 0x1130, 0x113e).into_iter().collect();
+    assert_eq!(starts, vec![0x1130, 0x1135, 0x113d]);
+
+    assert_eq!(branch_target("cbz\tw0, 4005a0 <main+0x20>"), Some(0x4005a0));
+    assert_eq!(branch_target("b.ne\t400580 <main+0x10>"), Some(0x400580));
+    assert_eq!(branch_target("bl\t400400 <puts@plt>"), None);
+    assert_eq!(branch_target("call   1030 <puts@plt>"), None);
+
+    assert!(matches_symbol("ruperf::annotate::run", "run"));
+    assert!(!matches_symbol("ruperf::annotate::rerun", "run"));
+}
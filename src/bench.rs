@@ -0,0 +1,351 @@
+//! # Bench driver.
+//! <p> Usage: <em> ruperf bench [COLLECTION] [BENCHMARK] [OPTION] </em>
+//! where COLLECTION is one of: </p>
+//! <ul>
+//! <li>sched: pipe, messaging</li>
+//! <li>syscall: basic</li>
+//! <li>mem: memcpy, memset</li>
+//! <li>all</li>
+//! </ul>
+
+mod mem;
+mod sched;
+mod syscall;
+
+extern crate structopt;
+use crate::bindings::*;
+use crate::event::open::*;
+use crate::event::utils::EventErr;
+use crate::stat::StatEvent;
+use crate::utils::ParseError;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// Configuration settings for running bench. See `./ruperf bench --help`
+/// for more information.
+#[derive(Debug, StructOpt)]
+pub struct BenchOptions {
+    #[structopt(help = "Collection to run: sched, syscall, mem or all")]
+    pub collection: String,
+
+    #[structopt(help = "Benchmark in the collection, all of them if not given")]
+    pub benchmark: Option<String>,
+
+    #[structopt(short, long, help = "Iterations, each benchmark has its own default")]
+    pub loops: Option<u64>,
+
+    #[structopt(
+        short,
+        long,
+        help = "Sender/receiver groups for sched messaging",
+        default_value = "2"
+    )]
+    pub groups: usize,
+
+    #[structopt(
+        short,
+        long,
+        help = "Buffer size for mem benchmarks, e.g. 4KB or 1MB",
+        default_value = "1MB",
+        parse(try_from_str = parse_size)
+    )]
+    pub size: usize,
+
+    #[structopt(long, help = "Count events while each benchmark runs")]
+    pub stat: bool,
+
+    #[structopt(
+        short,
+        long,
+        help = "Event to count, implies --stat",
+        number_of_values = 1
+    )]
+    pub event: Vec<StatEvent>,
+
+    #[structopt(short, long, help = "Format output as json")]
+    pub json: bool,
+}
+
+/// What a benchmark did.
+pub struct Ops {
+    pub ops: u64,
+    /// Bytes moved, for throughput benchmarks.
+    pub bytes: Option<u64>,
+    /// Description of one operation, e.g. "pipe round trips".
+    pub unit: String,
+}
+
+/// The timed part of a benchmark, returned by
+/// its untimed setup. Fails with what went wrong.
+pub type Timed = Box<dyn FnOnce() -> Result<Ops, String>>;
+
+/// A benchmark in a collection.
+struct Benchmark {
+    collection: &'static str,
+    name: &'static str,
+    run: fn(&BenchOptions) -> Timed,
+}
+
+const BENCHMARKS: &[Benchmark] = &[
+    Benchmark {
+        collection: "sched",
+        name: "pipe",
+        run: sched::pipe,
+    },
+    Benchmark {
+        collection: "sched",
+        name: "messaging",
+        run: sched::messaging,
+    },
+    Benchmark {
+        collection: "syscall",
+        name: "basic",
+        run: syscall::basic,
+    },
+    Benchmark {
+        collection: "mem",
+        name: "memcpy",
+        run: mem::memcpy,
+    },
+    Benchmark {
+        collection: "mem",
+        name: "memset",
+        run: mem::memset,
+    },
+];
+
+/// A counter read while a benchmark ran.
+#[derive(Debug, Serialize)]
+pub struct CounterResult {
+    pub event: String,
+    /// Count scaled up if the PMU was shared, `None`
+    /// if the event couldn't be counted or never ran.
+    pub value: Option<u64>,
+    pub running_percent: f64,
+    /// Why the event couldn't be counted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip)]
+    task_clock: bool,
+}
+
+/// Result of one benchmark, as printed with `--json`.
+#[derive(Debug, Serialize)]
+pub struct BenchResult {
+    pub benchmark: String,
+    pub unit: String,
+    pub ops: u64,
+    pub seconds: f64,
+    pub ops_per_sec: f64,
+    pub usecs_per_op: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_per_sec: Option<f64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub counters: Vec<CounterResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipc: Option<f64>,
+}
+
+/// Parse a size such as `4096`, `4KB` or `1M`.
+pub fn parse_size(s: &str) -> Result<usize, ParseError> {
+    let upper = s.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let scale = match &upper[digits.len()..] {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(ParseError::InvalidSize),
+    };
+    match digits.parse::<usize>() {
+        Ok(n) if n > 0 => n.checked_mul(scale).ok_or(ParseError::InvalidSize),
+        _ => Err(ParseError::InvalidSize),
+    }
+}
+
+/// Human readable byte count.
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.3} {}", value, UNITS[unit])
+}
+
+/// Set up `bench`, then time its run, counting `events` in
+/// this process and any threads or processes it starts.
+/// Counts are scaled up when the PMU was shared, as in
+/// stat. Events that can't be opened or read are left
+/// uncounted, with the reason.
+fn measure(
+    bench: &Benchmark,
+    options: &BenchOptions,
+    events: &[StatEvent],
+) -> Result<BenchResult, String> {
+    let timed = (bench.run)(options);
+    let counters: Vec<Result<Event, EventErr>> = events
+        .iter()
+        .map(|event| {
+            let attr = &mut event_open(event)?;
+            attr.set_inherit(1);
+            Event::with_attr(event.clone(), attr, None, -1)
+        })
+        .collect();
+    // Enable or disable `counter` and read it.
+    let read = |counter: &Result<Event, EventErr>, enable: bool| -> Result<CounterValue, String> {
+        let counter = counter.as_ref().map_err(ToString::to_string)?;
+        let toggled = if enable {
+            counter.fd.enable()
+        } else {
+            counter.fd.disable()
+        };
+        toggled
+            .and_then(|_| counter.fd.read_value())
+            .map_err(|e| e.to_string())
+    };
+    let start: Vec<_> = counters.iter().map(|c| read(c, true)).collect();
+    let now = Instant::now();
+    let ops = timed()?;
+    let elapsed: Duration = now.elapsed();
+    let stop: Vec<_> = counters.iter().map(|c| read(c, false)).collect();
+
+    let counters: Vec<CounterResult> = events
+        .iter()
+        .zip(start.into_iter().zip(stop))
+        .map(|(event, (start, stop))| {
+            let count = match (start, stop) {
+                (Ok(start), Ok(stop)) => Ok(stop - start),
+                (Err(err), _) | (_, Err(err)) => Err(err),
+            };
+            CounterResult {
+                event: event.to_string(),
+                value: count.as_ref().ok().and_then(CounterValue::scaled),
+                running_percent: count.as_ref().map_or(0.0, CounterValue::running_percent),
+                error: count.err(),
+                task_clock: event.is_software(perf_sw_ids_PERF_COUNT_SW_TASK_CLOCK),
+            }
+        })
+        .collect();
+    let value_of = |id| {
+        events
+            .iter()
+            .position(|e| e.is_hardware(id))
+            .and_then(|i| counters[i].value)
+    };
+    let ipc = match (
+        value_of(perf_hw_id_PERF_COUNT_HW_INSTRUCTIONS),
+        value_of(perf_hw_id_PERF_COUNT_HW_CPU_CYCLES),
+    ) {
+        (Some(i), Some(c)) if c > 0 => Some(i as f64 / c as f64),
+        _ => None,
+    };
+    let seconds = elapsed.as_secs_f64().max(f64::MIN_POSITIVE);
+    Ok(BenchResult {
+        benchmark: format!("{}/{}", bench.collection, bench.name),
+        unit: ops.unit,
+        ops: ops.ops,
+        seconds,
+        ops_per_sec: ops.ops as f64 / seconds,
+        usecs_per_op: seconds * 1_000_000.0 / ops.ops.max(1) as f64,
+        bytes_per_sec: ops.bytes.map(|b| b as f64 / seconds),
+        counters,
+        ipc,
+    })
+}
+
+/// Print one result for humans.
+fn print_result(result: &BenchResult) {
+    println!("# Running '{}' benchmark:", result.benchmark);
+    println!("# Executed {} {}\n", result.ops, result.unit);
+    println!("{:>16.3} [sec] total time", result.seconds);
+    println!("{:>16.3} usecs/op", result.usecs_per_op);
+    println!("{:>16.0} ops/sec", result.ops_per_sec);
+    if let Some(bytes) = result.bytes_per_sec {
+        println!("{:>16}/sec", format_bytes(bytes));
+    }
+    if !result.counters.is_empty() {
+        println!();
+        for c in &result.counters {
+            let running = match c.running_percent {
+                p if p < 100.0 => format!(" ({:.2}%)", p),
+                _ => String::new(),
+            };
+            match (c.value, &c.error) {
+                (None, Some(err)) => println!("{:>16} {} ({})", "<not supported>", c.event, err),
+                (None, None) => println!("{:>16} {}", "<not counted>", c.event),
+                (Some(value), _) if c.task_clock => println!(
+                    "{:>16.2} msec task-clock{}",
+                    value as f64 / 1_000_000.0,
+                    running
+                ),
+                (Some(value), _) => println!("{:>16} {}{}", value, c.event, running),
+            }
+        }
+        if let Some(ipc) = result.ipc {
+            println!("{:>16.2} insn per cycle", ipc);
+        }
+    }
+    println!();
+}
+
+/// Run perf bench.
+pub fn run_bench(options: BenchOptions) {
+    let selected: Vec<&Benchmark> = BENCHMARKS
+        .iter()
+        .filter(|b| options.collection == "all" || b.collection == options.collection)
+        .filter(|b| options.benchmark.as_ref().is_none_or(|n| b.name == n))
+        .collect();
+    if selected.is_empty() {
+        eprintln!("ruperf bench: unknown benchmark, available benchmarks:");
+        for b in BENCHMARKS {
+            eprintln!("  {} {}", b.collection, b.name);
+        }
+        std::process::exit(1);
+    }
+
+    let mut events = options.event.clone();
+    if options.stat && events.is_empty() {
+        events = vec![
+            StatEvent::named("cycles"),
+            StatEvent::named("instructions"),
+            StatEvent::named("task-clock"),
+        ];
+    }
+
+    let mut results = Vec::new();
+    for bench in selected {
+        let result = measure(bench, &options, &events).unwrap_or_else(|e| {
+            eprintln!("ruperf bench: {}/{}: {}", bench.collection, bench.name, e);
+            std::process::exit(1);
+        });
+        if !options.json {
+            print_result(&result);
+        }
+        results.push(result);
+    }
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&results).unwrap());
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn bench_test() {
+    assert_eq!(parse_size("4KB").unwrap(), 4096);
+    assert_eq!(parse_size("1m").unwrap(), 1 << 20);
+    assert_eq!(parse_size("512").unwrap(), 512);
+    assert!(parse_size("0").is_err());
+    assert!(parse_size("1XB").is_err());
+
+    let options = BenchOptions::from_iter(&["bench", "all", "--loops", "10", "--size", "4KB"]);
+    for bench in BENCHMARKS {
+        let result = measure(bench, &options, &[StatEvent::named("task-clock")]).unwrap();
+        assert!(result.ops > 0);
+        assert!(result.ops_per_sec > 0.0);
+        assert!(result.counters[0].value.unwrap() > 0);
+    }
+}
@@ -0,0 +1,43 @@
+//! Memory throughput benchmarks. Buffers are
+//! touched before timing starts, so page faults
+//! aren't counted against the copy.
+
+use crate::bench::{BenchOptions, Ops, Timed};
+use std::hint::black_box;
+
+/// Copy a `--size` buffer `loops` times.
+pub fn memcpy(options: &BenchOptions) -> Timed {
+    let loops = options.loops.unwrap_or(1000);
+    let size = options.size;
+    let src = vec![1_u8; size];
+    let mut dst = vec![0_u8; size];
+    Box::new(move || {
+        for _ in 0..loops {
+            dst.copy_from_slice(black_box(&src));
+            black_box(&mut dst);
+        }
+        Ok(Ops {
+            ops: loops,
+            bytes: Some(loops * size as u64),
+            unit: format!("memcpy()s of {} bytes", size),
+        })
+    })
+}
+
+/// Fill a `--size` buffer `loops` times.
+pub fn memset(options: &BenchOptions) -> Timed {
+    let loops = options.loops.unwrap_or(1000);
+    let size = options.size;
+    let mut dst = vec![1_u8; size];
+    Box::new(move || {
+        for i in 0..loops {
+            dst.fill(black_box(i as u8));
+            black_box(&mut dst);
+        }
+        Ok(Ops {
+            ops: loops,
+            bytes: Some(loops * size as u64),
+            unit: format!("memset()s of {} bytes", size),
+        })
+    })
+}
@@ -0,0 +1,123 @@
+//! Scheduler benchmarks: a pipe ping-pong between two
+//! processes, and groups of threads messaging each
+//! other over socket pairs.
+
+use crate::bench::{BenchOptions, Ops, Timed};
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::thread::{self, JoinHandle};
+
+/// Size of each `messaging` message.
+const MESSAGE_SIZE: usize = 100;
+
+/// Senders and receivers in each `messaging` group.
+const GROUP_SIZE: usize = 20;
+
+/// Bounce a byte between two processes over a pair
+/// of pipes, forcing a context switch each way.
+pub fn pipe(options: &BenchOptions) -> Timed {
+    let loops = options.loops.unwrap_or(100_000);
+    Box::new(move || -> Result<Ops, String> {
+        let pipe = || os_pipe::pipe().map_err(|e| format!("can't create a pipe: {}", e));
+        let (mut ping_reader, mut ping_writer) = pipe()?;
+        let (mut pong_reader, mut pong_writer) = pipe()?;
+        let mut buf = [0_u8; 1];
+        match unsafe { libc::fork() } {
+            -1 => return Err(format!("can't fork: {}", io::Error::last_os_error())),
+            0 => {
+                for _ in 0..loops {
+                    if ping_reader.read_exact(&mut buf).is_err()
+                        || pong_writer.write_all(&buf).is_err()
+                    {
+                        break;
+                    }
+                }
+                // Skip the parent's atexit handlers and buffers.
+                unsafe { libc::_exit(0) };
+            }
+            child => {
+                drop(ping_reader);
+                drop(pong_writer);
+                let bounced = (0..loops).try_for_each(|_| {
+                    ping_writer.write_all(&buf)?;
+                    pong_reader.read_exact(&mut buf)
+                });
+                // Closed first, so the child can't be
+                // left waiting for a byte on failure.
+                drop(ping_writer);
+                let mut status = 0;
+                unsafe { libc::waitpid(child, &mut status, 0) };
+                bounced.map_err(|e| format!("round trip failed: {}", e))?;
+            }
+        }
+        Ok(Ops {
+            ops: loops,
+            bytes: None,
+            unit: "pipe round trips between two processes".to_string(),
+        })
+    })
+}
+
+/// `groups` of senders each send `loops` messages to
+/// every receiver of their group, over socket pairs.
+pub fn messaging(options: &BenchOptions) -> Timed {
+    let loops = options.loops.unwrap_or(100);
+    let groups = options.groups.max(1);
+    Box::new(move || -> Result<Ops, String> {
+        let mut threads = Vec::new();
+        for _ in 0..groups {
+            let mut inputs = Vec::new();
+            for _ in 0..GROUP_SIZE {
+                let (tx, mut rx) =
+                    UnixStream::pair().map_err(|e| format!("can't create a socket pair: {}", e))?;
+                inputs.push(tx);
+                threads.push(spawn(move || {
+                    let mut buf = [0_u8; MESSAGE_SIZE];
+                    for _ in 0..loops * GROUP_SIZE as u64 {
+                        rx.read_exact(&mut buf)?;
+                    }
+                    Ok(())
+                })?);
+            }
+            for _ in 0..GROUP_SIZE {
+                let mut outputs: Vec<UnixStream> = inputs
+                    .iter()
+                    .map(UnixStream::try_clone)
+                    .collect::<io::Result<_>>()
+                    .map_err(|e| format!("can't clone a socket: {}", e))?;
+                threads.push(spawn(move || {
+                    let buf = [0_u8; MESSAGE_SIZE];
+                    for _ in 0..loops {
+                        for out in outputs.iter_mut() {
+                            out.write_all(&buf)?;
+                        }
+                    }
+                    Ok(())
+                })?);
+            }
+        }
+        for t in threads {
+            t.join()
+                .map_err(|_| "a thread panicked".to_string())?
+                .map_err(|e| format!("passing a message failed: {}", e))?;
+        }
+        Ok(Ops {
+            ops: groups as u64 * (GROUP_SIZE * GROUP_SIZE) as u64 * loops,
+            bytes: None,
+            unit: format!(
+                "{} byte messages in {} groups of {} senders and receivers",
+                MESSAGE_SIZE, groups, GROUP_SIZE
+            ),
+        })
+    })
+}
+
+/// Start a `messaging` sender or receiver.
+fn spawn<F>(f: F) -> Result<JoinHandle<io::Result<()>>, String>
+where
+    F: FnOnce() -> io::Result<()> + Send + 'static,
+{
+    thread::Builder::new()
+        .spawn(f)
+        .map_err(|e| format!("can't start a thread: {}", e))
+}
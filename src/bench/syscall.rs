@@ -0,0 +1,19 @@
+//! System call benchmarks.
+
+use crate::bench::{BenchOptions, Ops, Timed};
+
+/// Call `getppid()` directly through `syscall()`,
+/// the cheapest round trip into the kernel.
+pub fn basic(options: &BenchOptions) -> Timed {
+    let loops = options.loops.unwrap_or(1_000_000);
+    Box::new(move || {
+        for _ in 0..loops {
+            unsafe { libc::syscall(libc::SYS_getppid) };
+        }
+        Ok(Ops {
+            ops: loops,
+            bytes: None,
+            unit: "getppid() system calls".to_string(),
+        })
+    })
+}
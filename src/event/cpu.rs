@@ -0,0 +1,80 @@
+//! Helpers for working out which CPUs
+//! events can be opened on. Sampling an
+//! inherited event requires one event per
+//! CPU, since the kernel refuses to mmap
+//! an inherited event opened with `cpu = -1`.
+
+use std::fs;
+
+/// CPUs the kernel currently has online.
+const ONLINE_CPUS: &str = "/sys/devices/system/cpu/online";
+
+/// Where each CPU's topology files live.
+const CPU_DIR: &str = "/sys/devices/system/cpu";
+
+/// Which socket and core a CPU belongs to.
+/// Hyperthreads of one core share both.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Topology {
+    pub socket: i32,
+    pub core: i32,
+}
+
+/// Parse a kernel style CPU list such
+/// as `0,2-4` into `[0, 2, 3, 4]`.
+/// Returns `None` if the list is malformed.
+pub fn parse_cpu_list(list: &str) -> Option<Vec<i32>> {
+    let mut cpus = Vec::new();
+    for part in list.trim().split(',') {
+        match part.split_once('-') {
+            Some((lo, hi)) => {
+                let lo: i32 = lo.trim().parse().ok()?;
+                let hi: i32 = hi.trim().parse().ok()?;
+                if lo > hi {
+                    return None;
+                }
+                cpus.extend(lo..=hi);
+            }
+            None => cpus.push(part.trim().parse().ok()?),
+        }
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Some(cpus)
+}
+
+/// Every online CPU. Falls back to `0..nproc`
+/// if sysfs isn't available.
+pub fn online_cpus() -> Vec<i32> {
+    fs::read_to_string(ONLINE_CPUS)
+        .ok()
+        .and_then(|s| parse_cpu_list(&s))
+        .unwrap_or_else(|| {
+            let n = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+            (0..n.max(1) as i32).collect()
+        })
+}
+
+/// Topology of `cpu`. Without sysfs every CPU is
+/// taken to be its own core on socket 0.
+pub fn topology(cpu: i32) -> Topology {
+    let read = |file: &str| -> Option<i32> {
+        let path = format!("{}/cpu{}/topology/{}", CPU_DIR, cpu, file);
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    };
+    Topology {
+        socket: read("physical_package_id").unwrap_or(0),
+        core: read("core_id").unwrap_or(cpu),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn parse_cpu_list_test() {
+    assert_eq!(parse_cpu_list("0"), Some(vec![0]));
+    assert_eq!(parse_cpu_list("0,2-4\n"), Some(vec![0, 2, 3, 4]));
+    assert_eq!(parse_cpu_list("3-1"), None);
+    assert_eq!(parse_cpu_list("a"), None);
+    assert!(!online_cpus().is_empty());
+    assert!(topology(online_cpus()[0]).socket >= 0);
+}
@@ -8,40 +8,125 @@
 
 extern crate libc;
 use crate::bindings::*;
+use crate::event::ring::RingBuffer;
 use crate::event::sys::linux::*;
 use crate::event::sys::wrapper::*;
 use crate::event::utils::*;
 use libc::{c_int, c_ulong, pid_t, syscall, SYS_perf_event_open};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
 
-/// Stores a raw file descriptor
+/// Owns a raw file descriptor
 /// for use in various `perf_event_open()`
-/// system call wrappers.
+/// system call wrappers, along with the
+/// `read_format` its counts are read in.
+/// The descriptor is closed on drop.
 #[derive(Debug)]
-pub struct FileDesc(i32);
+pub struct FileDesc {
+    fd: i32,
+    read_format: u64,
+}
+
+/// A counter reading, with the times needed
+/// to scale it when the PMU was multiplexed.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CounterValue {
+    pub value: u64,
+    /// Nanoseconds the event was enabled.
+    pub enabled: u64,
+    /// Nanoseconds the event was actually
+    /// scheduled on the PMU.
+    pub running: u64,
+    /// Kernel id of the event, with `PERF_FORMAT_ID`.
+    pub id: u64,
+    /// Samples the event lost, with `PERF_FORMAT_LOST`.
+    pub lost: u64,
+}
+
+/// Counts of one event on several CPUs add up,
+/// along with the times they were enabled and running
+/// and the samples lost. The total keeps its own id.
+impl std::ops::AddAssign for CounterValue {
+    fn add_assign(&mut self, other: Self) {
+        self.value += other.value;
+        self.enabled += other.enabled;
+        self.running += other.running;
+        self.lost += other.lost;
+    }
+}
+
+/// The change in a counter between two readings.
+impl std::ops::Sub for CounterValue {
+    type Output = Self;
+    fn sub(self, earlier: Self) -> Self {
+        CounterValue {
+            value: self.value.saturating_sub(earlier.value),
+            enabled: self.enabled.saturating_sub(earlier.enabled),
+            running: self.running.saturating_sub(earlier.running),
+            id: self.id,
+            lost: self.lost.saturating_sub(earlier.lost),
+        }
+    }
+}
+
+impl CounterValue {
+    /// The count extrapolated over the whole time the
+    /// event was enabled. `None` if it never ran.
+    pub fn scaled(&self) -> Option<u64> {
+        if self.running == 0 {
+            return None;
+        }
+        if self.running >= self.enabled {
+            return Some(self.value);
+        }
+        Some((self.value as u128 * self.enabled as u128 / self.running as u128) as u64)
+    }
+
+    /// Percentage of the enabled time
+    /// the event was on the PMU.
+    pub fn running_percent(&self) -> f64 {
+        if self.enabled == 0 {
+            return 100.0;
+        }
+        self.running as f64 * 100.0 / self.enabled as f64
+    }
+}
 
 impl FileDesc {
     /// Set up performance monitoring for
-    /// configured event without any flags.
-    /// Panics if `perf_event_open()` fails.
-    pub fn new(event: &mut perf_event_attr, pid: Option<i32>, cpu: i32, group_fd: i32) -> Self {
-        let ret: i32;
+    /// configured event. The descriptor is
+    /// closed on exec, so it doesn't leak
+    /// into commands started afterwards.
+    /// Returns the `errno` of a failed
+    /// `perf_event_open()` in the error.
+    pub fn new(
+        event: &mut perf_event_attr,
+        pid: Option<i32>,
+        cpu: i32,
+        group_fd: i32,
+    ) -> Result<Self, SysErr> {
         let pid = match pid {
             Some(x) => x as pid_t,
             None => 0_i32,
         };
-        ret = perf_event_open(event, pid as pid_t, cpu, group_fd, 0) as i32;
+        let flags = PERF_FLAG_FD_CLOEXEC as usize;
+        let ret = perf_event_open(event, pid as pid_t, cpu, group_fd, flags) as i32;
         if ret == -1 {
-            panic!("Panic: system call perf_event_open() failed in FileDesc::new()");
+            return Err(SysErr::last_open());
         }
-        Self(ret)
+        Ok(Self {
+            fd: ret,
+            read_format: event.read_format,
+        })
     }
     /// Enable the performance counter
     /// associated with `fd`.
     pub fn enable(&self) -> Result<(), SysErr> {
         let ret: i32;
-        ret = unsafe { libc::ioctl(self.0, ENABLE as u64, 0) };
+        ret = unsafe { libc::ioctl(self.fd, ENABLE as u64, 0) };
         if ret == -1 {
-            return Err(SysErr::IoFail);
+            return Err(SysErr::last_io());
         }
         Ok(())
     }
@@ -49,9 +134,33 @@ impl FileDesc {
     /// associated with `fd`.
     pub fn disable(&self) -> Result<(), SysErr> {
         let ret: i32;
-        ret = unsafe { libc::ioctl(self.0, DISABLE as u64, 0) };
+        ret = unsafe { libc::ioctl(self.fd, DISABLE as u64, 0) };
         if ret == -1 {
-            return Err(SysErr::IoFail);
+            return Err(SysErr::last_io());
+        }
+        Ok(())
+    }
+    /// Enable every counter in the group
+    /// led by `fd`.
+    pub fn enable_group(&self) -> Result<(), SysErr> {
+        self.group_ioctl(ENABLE)
+    }
+    /// Disable every counter in the group
+    /// led by `fd`.
+    pub fn disable_group(&self) -> Result<(), SysErr> {
+        self.group_ioctl(DISABLE)
+    }
+    /// Reset every counter in the group
+    /// led by `fd` to 0.
+    pub fn reset_group(&self) -> Result<(), SysErr> {
+        self.group_ioctl(RESET)
+    }
+    /// Apply an argumentless ioctl to
+    /// the whole group led by `fd`.
+    fn group_ioctl(&self, request: u32) -> Result<(), SysErr> {
+        let flag = perf_event_ioc_flags_PERF_IOC_FLAG_GROUP as c_ulong;
+        if unsafe { libc::ioctl(self.fd, request as u64, flag) } == -1 {
+            return Err(SysErr::last_io());
         }
         Ok(())
     }
@@ -69,18 +178,18 @@ impl FileDesc {
             return Err(SysErr::IoArg);
         }
         let arg: *const usize = &count;
-        ret = unsafe { libc::ioctl(self.0, REFRESH as u64, arg) };
+        ret = unsafe { libc::ioctl(self.fd, REFRESH as u64, arg) };
         if ret == -1 {
-            return Err(SysErr::IoFail);
+            return Err(SysErr::last_io());
         }
         Ok(())
     }
     /// Reset the performance counter to 0.
     pub fn reset(&self) -> Result<(), SysErr> {
         let ret: i32;
-        ret = unsafe { libc::ioctl(self.0, RESET as u64, 0) };
+        ret = unsafe { libc::ioctl(self.fd, RESET as u64, 0) };
         if ret == -1 {
-            return Err(SysErr::IoFail);
+            return Err(SysErr::last_io());
         }
         Ok(())
     }
@@ -91,21 +200,23 @@ impl FileDesc {
     pub fn overflow_period(&self, interval: usize) -> Result<(), SysErr> {
         let ret: i32;
         let arg: *const usize = &interval;
-        ret = unsafe { libc::ioctl(self.0, PERIOD as u64, arg) };
+        ret = unsafe { libc::ioctl(self.fd, PERIOD as u64, arg) };
         if ret == -1 {
-            return Err(SysErr::IoFail);
+            return Err(SysErr::last_io());
         }
         Ok(())
     }
-    /// Report counter information to
-    /// specific file descriptor.
-    pub fn set_output(&self) -> Result<(), SysErr> {
-        todo!()
+    /// Report samples and records into the
+    /// ring buffer of `target` instead of
+    /// one mapped for `fd`. Both must count
+    /// on the same CPU, or the same task.
+    pub fn set_output(&self, target: &FileDesc) -> Result<(), SysErr> {
+        self.ioctl_with(SET_OUTPUT, target.fd as c_ulong)
     }
     /// Ignore counter output for event
-    /// associated with `fd`.
+    /// associated with `fd`, undoing `set_output()`.
     pub fn ignore_output(&self) -> Result<(), SysErr> {
-        todo!()
+        self.ioctl_with(SET_OUTPUT, -1_i64 as c_ulong)
     }
     /// Return event ID value
     /// associated with `fd`.
@@ -114,8 +225,8 @@ impl FileDesc {
         let mut ret: usize = 0;
         ret = unsafe {
             let result: *mut usize = &mut ret;
-            if libc::ioctl(self.0, ID as u64, result) == -1 {
-                return Err(SysErr::IoFail);
+            if libc::ioctl(self.fd, ID as u64, result) == -1 {
+                return Err(SysErr::last_io());
             }
             *result
         };
@@ -126,28 +237,197 @@ impl FileDesc {
     }
     /// Pause writing to ring-buffer
     /// for associated file descriptor.
+    /// Records are dropped while paused.
     pub fn pause_output(&self) -> Result<(), SysErr> {
-        todo!()
+        self.ioctl_with(PAUSE_OUTPUT, 1)
     }
     /// Resume writing to ring-buffer
     /// for associated file descriptor.
     pub fn resume_output(&self) -> Result<(), SysErr> {
-        todo!()
+        self.ioctl_with(PAUSE_OUTPUT, 0)
     }
     /// Modify the attributes for
-    /// a specified event.
-    pub fn modify_attributes(&self, _event: *const perf_event_attr) -> Result<(), SysErr> {
-        todo!()
+    /// a specified event. The kernel
+    /// only allows this for breakpoints.
+    pub fn modify_attributes(&self, event: &perf_event_attr) -> Result<(), SysErr> {
+        let arg: *const perf_event_attr = event;
+        self.ioctl_with(MODIFY_ATTRIBUTES, arg as c_ulong)
+    }
+    /// Only count tracepoint hits matching
+    /// `filter`, e.g. `prev_pid != 0`.
+    pub fn set_filter(&self, filter: &str) -> Result<(), SysErr> {
+        let filter = CString::new(filter).map_err(|_| SysErr::IoArg)?;
+        self.ioctl_with(SET_FILTER, filter.as_ptr() as c_ulong)
+    }
+    /// Attach the BPF program loaded
+    /// as `prog_fd` to a tracepoint or
+    /// kprobe event.
+    pub fn set_bpf(&self, prog_fd: RawFd) -> Result<(), SysErr> {
+        if prog_fd < 0 {
+            return Err(SysErr::IoArg);
+        }
+        self.ioctl_with(SET_BPF, prog_fd as c_ulong)
+    }
+    /// Ids of the BPF programs attached
+    /// to the tracepoint, at most `max`.
+    pub fn query_bpf(&self, max: u32) -> Result<Vec<u32>, SysErr> {
+        // { ids_len, prog_cnt, ids[ids_len] }
+        let mut buf = vec![0_u32; 2 + max as usize];
+        buf[0] = max;
+        self.ioctl_with(QUERY_BPF, buf.as_mut_ptr() as c_ulong)?;
+        let count = buf[1].min(max) as usize;
+        Ok(buf[2..2 + count].to_vec())
+    }
+    /// Apply an ioctl taking `arg`,
+    /// a value or pointer, to `fd`.
+    fn ioctl_with(&self, request: u32, arg: c_ulong) -> Result<(), SysErr> {
+        if unsafe { libc::ioctl(self.fd, request as u64, arg) } == -1 {
+            return Err(SysErr::last_io());
+        }
+        Ok(())
+    }
+    /// Map the sampling ring buffer for
+    /// `fd` into memory, with `pages` data
+    /// pages. `pages` must be a power of two.
+    pub fn mmap(&self, pages: usize) -> Result<RingBuffer<'_>, SysErr> {
+        RingBuffer::new(self, pages)
     }
     /// Read counter value associated
     /// with field of `FileDesc` caller.
     pub fn read(&self) -> Result<isize, SysErr> {
-        let ret = read_wrap(self.0);
-        if ret == -1 {
+        Ok(self.read_value()?.value as isize)
+    }
+    /// Read the counter along with every field its
+    /// `read_format` asks for. Counters opened with
+    /// `PERF_FORMAT_GROUP` are read with `read_group()`.
+    pub fn read_value(&self) -> Result<CounterValue, SysErr> {
+        if self.read_format & GROUP_FORMAT != 0 {
+            return Err(SysErr::IoArg);
+        }
+        let mut buf = vec![0_u64; read_len(self.read_format, 1)];
+        self.read_exact(&mut buf)?;
+        Ok(decode(self.read_format, &buf)?[0])
+    }
+    /// Read every counter in the group led by
+    /// `fd`, which must have been opened with a
+    /// `read_format` including `PERF_FORMAT_GROUP`.
+    /// Returns the values of the `members` counters,
+    /// leader first. Members share the group's enabled
+    /// and running times.
+    pub fn read_group(&self, members: usize) -> Result<Vec<CounterValue>, SysErr> {
+        if self.read_format & GROUP_FORMAT == 0 {
+            return Err(SysErr::IoArg);
+        }
+        let mut buf = vec![0_u64; read_len(self.read_format, members)];
+        self.read_exact(&mut buf)?;
+        let values = decode(self.read_format, &buf)?;
+        if values.len() != members {
             return Err(SysErr::ReadFail);
         }
-        Ok(ret)
+        Ok(values)
+    }
+    /// Fill `buf` with one `read()`, failing
+    /// if the kernel returned any less.
+    fn read_exact(&self, buf: &mut [u64]) -> Result<(), SysErr> {
+        let wanted = std::mem::size_of_val(buf);
+        let read = read_buf(self.fd, buf)?;
+        if read != wanted {
+            return Err(SysErr::ShortRead(read, wanted));
+        }
+        Ok(())
+    }
+}
+
+impl AsRawFd for FileDesc {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+/// Hand the descriptor over without closing it.
+impl IntoRawFd for FileDesc {
+    fn into_raw_fd(self) -> RawFd {
+        let fd = self.fd;
+        std::mem::forget(self);
+        fd
+    }
+}
+
+impl Drop for FileDesc {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// `read_format` flags that add fields to a read.
+const TIMES_FORMAT: u64 = (perf_event_read_format_PERF_FORMAT_TOTAL_TIME_ENABLED
+    | perf_event_read_format_PERF_FORMAT_TOTAL_TIME_RUNNING) as u64;
+const GROUP_FORMAT: u64 = perf_event_read_format_PERF_FORMAT_GROUP as u64;
+const ID_FORMAT: u64 = perf_event_read_format_PERF_FORMAT_ID as u64;
+/// `PERF_FORMAT_LOST`, the samples an event lost, since
+/// Linux 6.0. Older kernel headers don't define it.
+pub const LOST_FORMAT: u64 = 1 << 4;
+
+/// How many `u64`s a read of `members` counters returns
+/// with `read_format`.
+pub fn read_len(read_format: u64, members: usize) -> usize {
+    let times = (read_format & TIMES_FORMAT).count_ones() as usize;
+    let per_counter = 1 + (read_format & (ID_FORMAT | LOST_FORMAT)).count_ones() as usize;
+    if read_format & GROUP_FORMAT != 0 {
+        1 + times + members * per_counter
+    } else {
+        times + per_counter
+    }
+}
+
+/// Decode what a read of counters opened with
+/// `read_format` returned, one value per counter:
+///
+/// `{ value, [time_enabled], [time_running], [id], [lost] }`, or with
+/// `PERF_FORMAT_GROUP` `{ nr, [time_enabled], [time_running],
+/// { value, [id], [lost] } * nr }`.
+pub fn decode(read_format: u64, buf: &[u64]) -> Result<Vec<CounterValue>, SysErr> {
+    let group = read_format & GROUP_FORMAT != 0;
+    let members = match buf.first() {
+        Some(&nr) if group => nr as usize,
+        _ => 1,
+    };
+    if buf.len() != read_len(read_format, members) {
+        return Err(SysErr::ShortRead(
+            buf.len() * 8,
+            read_len(read_format, members) * 8,
+        ));
+    }
+    let has = |flag: u64| read_format & flag != 0;
+    let mut fields = buf.iter().copied();
+    let mut next_if = |wanted: bool| if wanted { fields.next() } else { None };
+    // A lone counter's value comes first, a group's size.
+    let first = next_if(true);
+    let enabled = next_if(has(
+        perf_event_read_format_PERF_FORMAT_TOTAL_TIME_ENABLED as u64
+    ));
+    let running = next_if(has(
+        perf_event_read_format_PERF_FORMAT_TOTAL_TIME_RUNNING as u64
+    ));
+    // Without times, assume the counter always ran.
+    let (enabled, running) = match (enabled, running) {
+        (Some(e), Some(r)) => (e, r),
+        (Some(e), None) => (e, e),
+        (None, Some(r)) => (r, r),
+        (None, None) => (0, 0),
+    };
+    let mut values = Vec::with_capacity(members);
+    for _ in 0..members {
+        let value = if group { next_if(true) } else { first };
+        values.push(CounterValue {
+            value: value.unwrap_or(0),
+            enabled,
+            running,
+            id: next_if(has(ID_FORMAT)).unwrap_or(0),
+            lost: next_if(has(LOST_FORMAT)).unwrap_or(0),
+        });
     }
+    Ok(values)
 }
 
 /// For documentation on `perf_event_open()`
@@ -216,3 +496,281 @@ fn read_test() {
     assert_ne!(cnt, 0);
     assert!(cnt > 0, "cnt = {}", cnt);
 }
+
+#[test]
+fn counter_value_scaled_test() {
+    let half = CounterValue {
+        value: 100,
+        enabled: 2_000,
+        running: 1_000,
+        ..Default::default()
+    };
+    assert_eq!(half.scaled(), Some(200));
+    assert_eq!(half.running_percent(), 50.0);
+    let never = CounterValue {
+        value: 0,
+        enabled: 2_000,
+        running: 0,
+        ..Default::default()
+    };
+    assert_eq!(never.scaled(), None);
+}
+
+#[test]
+fn read_format_test() {
+    let times = TIMES_FORMAT;
+    let lone = decode(times | ID_FORMAT | LOST_FORMAT, &[7, 20, 10, 99, 3]).unwrap();
+    assert_eq!(
+        lone,
+        vec![CounterValue {
+            value: 7,
+            enabled: 20,
+            running: 10,
+            id: 99,
+            lost: 3,
+        }]
+    );
+    let group = decode(GROUP_FORMAT | times | ID_FORMAT, &[2, 20, 10, 7, 98, 8, 99]).unwrap();
+    assert_eq!((group[0].value, group[0].id), (7, 98));
+    assert_eq!((group[1].value, group[1].id, group[1].running), (8, 99, 10));
+    assert_eq!(decode(times, &[7, 20]), Err(SysErr::ShortRead(16, 24)));
+    assert!(decode(GROUP_FORMAT, &[3, 1, 2]).is_err());
+
+    // Ids read back match what the kernel says they are.
+    let attr = || {
+        let mut attr = perf_event_attr {
+            type_: perf_type_id_PERF_TYPE_SOFTWARE,
+            size: std::mem::size_of::<perf_event_attr>() as u32,
+            config: perf_sw_ids_PERF_COUNT_SW_TASK_CLOCK as u64,
+            read_format: TIMES_FORMAT | ID_FORMAT | GROUP_FORMAT,
+            ..Default::default()
+        };
+        attr.set_disabled(1);
+        attr
+    };
+    let leader = FileDesc::new(&mut attr(), None, -1, -1).unwrap();
+    let member = FileDesc::new(&mut attr(), None, -1, leader.fd).unwrap();
+    leader.enable_group().unwrap();
+    let values = leader.read_group(2).unwrap();
+    assert_eq!(values[0].id, leader.id().unwrap() as u64);
+    assert_eq!(values[1].id, member.id().unwrap() as u64);
+    assert_eq!(leader.read_group(3), Err(SysErr::ShortRead(56, 72)));
+    assert_eq!(leader.read_value(), Err(SysErr::IoArg));
+}
+
+#[test]
+fn close_on_drop_test() {
+    let attr = || perf_event_attr {
+        type_: perf_type_id_PERF_TYPE_SOFTWARE,
+        size: std::mem::size_of::<perf_event_attr>() as u32,
+        config: perf_sw_ids_PERF_COUNT_SW_TASK_CLOCK as u64,
+        ..Default::default()
+    };
+    // In a child limited to a few descriptors, open many
+    // times more events than that, so leaking even one
+    // in a hundred runs out of them.
+    match unsafe { libc::fork() } {
+        -1 => panic!("fork failed"),
+        0 => {
+            let limit = libc::rlimit {
+                rlim_cur: 32,
+                rlim_max: 32,
+            };
+            let mut ok = unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } == 0;
+            for _ in 0..5000 {
+                ok &= FileDesc::new(&mut attr(), None, -1, -1).is_ok();
+            }
+            unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+        }
+        child => {
+            let mut status = 0;
+            assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+            assert!(libc::WIFEXITED(status));
+            assert_eq!(libc::WEXITSTATUS(status), 0);
+        }
+    }
+
+    let fd = FileDesc::new(&mut attr(), None, -1, -1).unwrap();
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+    assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+    let raw = fd.into_raw_fd();
+    assert_ne!(unsafe { libc::fcntl(raw, libc::F_GETFD) }, -1);
+    unsafe { libc::close(raw) };
+}
+
+#[test]
+fn output_ioctl_test() {
+    let sampling = || {
+        let mut attr = perf_event_attr {
+            type_: perf_type_id_PERF_TYPE_SOFTWARE,
+            size: std::mem::size_of::<perf_event_attr>() as u32,
+            config: perf_sw_ids_PERF_COUNT_SW_TASK_CLOCK as u64,
+            __bindgen_anon_1: perf_event_attr__bindgen_ty_1 {
+                sample_period: 100_000,
+            },
+            sample_type: perf_event_sample_format_PERF_SAMPLE_IP,
+            ..Default::default()
+        };
+        attr.set_disabled(1);
+        attr
+    };
+    let spin = || {
+        let now = std::time::Instant::now();
+        while now.elapsed().as_millis() < 10 {}
+    };
+    let leader = FileDesc::new(&mut sampling(), None, -1, -1).unwrap();
+    let other = FileDesc::new(&mut sampling(), None, -1, -1).unwrap();
+    let mut ring = leader.mmap(8).unwrap();
+
+    // Samples of `other` land in the leader's buffer.
+    other.set_output(&leader).unwrap();
+    other.enable().unwrap();
+    spin();
+    other.disable().unwrap();
+    assert!(!ring.drain().is_empty());
+    other.ignore_output().unwrap();
+
+    // Nothing is written while paused.
+    leader.pause_output().unwrap();
+    leader.enable().unwrap();
+    spin();
+    leader.disable().unwrap();
+    assert!(ring.drain().is_empty());
+    leader.resume_output().unwrap();
+    leader.enable().unwrap();
+    spin();
+    leader.disable().unwrap();
+    assert!(!ring.drain().is_empty());
+
+    // The rest only apply to breakpoints and tracepoints,
+    // which the kernel says by rejecting the argument.
+    let invalid = Err(SysErr::IoFail(libc::EINVAL));
+    assert_eq!(other.modify_attributes(&sampling()), invalid);
+    assert_eq!(other.set_filter("common_pid != 0"), invalid);
+    assert_eq!(other.set_filter("nul\0byte"), Err(SysErr::IoArg));
+    assert_eq!(other.set_bpf(-1), Err(SysErr::IoArg));
+    assert_eq!(other.set_bpf(leader.as_raw_fd()), invalid);
+    // Without CAP_PERFMON the kernel refuses before looking at the event.
+    let query = other.query_bpf(4);
+    assert!(
+        query == Err(SysErr::IoFail(libc::EINVAL)) || query == Err(SysErr::IoFail(libc::EPERM)),
+        "query_bpf: {:?}",
+        query
+    );
+}
+
+#[test]
+fn modify_attributes_test() {
+    // Write watchpoints on our own memory need no privilege.
+    const HW_BREAKPOINT_W: u32 = 2;
+    const HW_BREAKPOINT_LEN_8: u64 = 8;
+    let watched = [std::cell::Cell::new(0_u64), std::cell::Cell::new(0_u64)];
+    let watchpoint = |addr: &std::cell::Cell<u64>| {
+        let mut attr = perf_event_attr {
+            type_: perf_type_id_PERF_TYPE_BREAKPOINT,
+            size: std::mem::size_of::<perf_event_attr>() as u32,
+            bp_type: HW_BREAKPOINT_W,
+            __bindgen_anon_3: perf_event_attr__bindgen_ty_3 {
+                bp_addr: addr.as_ptr() as u64,
+            },
+            __bindgen_anon_4: perf_event_attr__bindgen_ty_4 {
+                bp_len: HW_BREAKPOINT_LEN_8,
+            },
+            ..Default::default()
+        };
+        attr.set_exclude_kernel(1);
+        attr.set_exclude_hv(1);
+        attr
+    };
+    let fd = FileDesc::new(&mut watchpoint(&watched[0]), None, -1, -1).unwrap();
+    let write = |cell: &std::cell::Cell<u64>| {
+        for i in 0..3 {
+            unsafe { std::ptr::write_volatile(cell.as_ptr(), i) };
+        }
+    };
+    write(&watched[0]);
+    assert_eq!(fd.read().unwrap(), 3);
+
+    // Moved to the other variable, writes to the first aren't seen.
+    fd.modify_attributes(&watchpoint(&watched[1])).unwrap();
+    write(&watched[0]);
+    assert_eq!(fd.read().unwrap(), 3);
+    write(&watched[1]);
+    assert_eq!(fd.read().unwrap(), 6);
+}
+
+#[test]
+fn set_filter_test() {
+    use crate::event::open::Event;
+    use crate::stat::StatEvent;
+    use std::str::FromStr;
+
+    // Tracepoints need tracefs, skip without it.
+    let tracepoint = match StatEvent::from_str("syscalls:sys_enter_getpid") {
+        Ok(tracepoint) => tracepoint,
+        Err(err) => {
+            eprintln!("set_filter_test skipped: {:?}", err);
+            return;
+        }
+    };
+    let count_getpid = |filter: &str| {
+        let event = Event::new(tracepoint.clone(), None).unwrap();
+        event.fd.set_filter(filter).unwrap();
+        event.fd.enable().unwrap();
+        for _ in 0..5 {
+            unsafe { libc::syscall(libc::SYS_getpid) };
+        }
+        event.fd.disable().unwrap();
+        event.fd.read().unwrap()
+    };
+    assert_eq!(count_getpid("common_pid != 0"), 5);
+    assert_eq!(count_getpid("common_pid == 0"), 0);
+    let event = Event::new(tracepoint.clone(), None).unwrap();
+    assert_eq!(
+        event.fd.set_filter("no_such_field == 1"),
+        Err(SysErr::IoFail(libc::EINVAL))
+    );
+}
+
+#[test]
+fn set_bpf_test() {
+    use crate::event::open::Event;
+    use crate::stat::StatEvent;
+    use std::str::FromStr;
+
+    let tracepoint = match StatEvent::from_str("syscalls:sys_enter_getpid") {
+        Ok(tracepoint) => tracepoint,
+        Err(err) => {
+            eprintln!("set_bpf_test skipped: {:?}", err);
+            return;
+        }
+    };
+    // `r0 = 0; exit`, loaded as a tracepoint program.
+    const BPF_PROG_LOAD: c_int = 5;
+    const BPF_PROG_TYPE_TRACEPOINT: u64 = 5;
+    let insns: [u64; 2] = [0xb7, 0x95];
+    let license = b"GPL\0";
+    let mut attr = [0_u64; 16];
+    attr[0] = BPF_PROG_TYPE_TRACEPOINT | ((insns.len() as u64) << 32);
+    attr[1] = insns.as_ptr() as u64;
+    attr[2] = license.as_ptr() as u64;
+    let prog = unsafe {
+        syscall(
+            libc::SYS_bpf,
+            BPF_PROG_LOAD,
+            attr.as_mut_ptr(),
+            std::mem::size_of_val(&attr),
+        )
+    } as RawFd;
+    if prog < 0 {
+        eprintln!("set_bpf_test skipped: {:?}", SysErr::last_io());
+        return;
+    }
+    let event = Event::new(tracepoint, None).unwrap();
+    assert_eq!(event.fd.query_bpf(4), Ok(vec![]));
+    event.fd.set_bpf(prog).unwrap();
+    assert_eq!(event.fd.query_bpf(4).unwrap().len(), 1);
+    // Only one program per event.
+    assert_eq!(event.fd.set_bpf(prog), Err(SysErr::IoFail(libc::EEXIST)));
+    unsafe { libc::close(prog) };
+}
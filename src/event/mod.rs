@@ -6,10 +6,16 @@
 // Disable cargo build warnings created due to using bindgen.
 #![allow(dead_code)]
 
+pub mod cpu;
 mod fd;
 pub mod open;
+pub mod rdpmc;
+pub mod ring;
+pub mod sample;
 mod sys;
-mod utils;
+pub mod task;
+pub mod tracepoint;
+pub mod utils;
 
 pub fn perf_event_hello() {
     println!("hello from your friendly perf_event file");
@@ -31,8 +37,7 @@ fn wrapper_test() {
     event.set_disabled(1);
     event.set_exclude_kernel(1);
     event.set_exclude_hv(1);
-    // Panic on failure.
-    let fd = fd::FileDesc::new(event, Some(0), -1, -1);
+    let fd = fd::FileDesc::new(event, Some(0), -1, -1).unwrap();
     // Make sure ioctls are working.
     fd.reset().unwrap();
     fd.disable().unwrap();
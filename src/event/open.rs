@@ -7,11 +7,17 @@
 
 use crate::bindings::*;
 use crate::event::fd;
+pub use crate::event::fd::CounterValue;
+pub use crate::event::rdpmc::UserCounter;
 use crate::event::utils::*;
 use crate::stat::StatEvent;
+use std::os::unix::io::AsRawFd;
 
 const PERF_EVENT_ATTR_SIZE: u32 = std::mem::size_of::<perf_event_attr>() as u32;
 
+const TIMES_READ_FORMAT: u64 = (perf_event_read_format_PERF_FORMAT_TOTAL_TIME_ENABLED
+    | perf_event_read_format_PERF_FORMAT_TOTAL_TIME_RUNNING) as u64;
+
 ///Event enum contains file descriptor and event type
 //simple starting options. Add more as needed
 pub struct Event {
@@ -19,140 +25,110 @@ pub struct Event {
     pub event: StatEvent,
 }
 
-/// Initialize perf attributes. Currently set up to match on intended event.
+/// Initialize perf attributes for the parsed `event`.
 /// Returns the initialized perf_event_attr data structure or an error.
 pub fn event_open(event: &StatEvent) -> Result<perf_event_attr, EventErr> {
-    // To measure hardware CPU cache events
-    // when `type_` is set to `PERF_TYPE_HW_CACHE`
-    // the value of config must be computed.
-    // See the `perf_event_open()` man page for details.
-    let cache_config = |id, op, rs| (id as u64) | ((op as u64) << 8) | ((rs as u64) << 16);
-    match &event {
-        StatEvent::Cycles => {
-            let event_open = &mut perf_event_attr {
-                type_: perf_type_id_PERF_TYPE_HARDWARE,
-                size: PERF_EVENT_ATTR_SIZE,
-                config: perf_hw_id_PERF_COUNT_HW_CPU_CYCLES as u64,
-                ..Default::default()
-            };
-            event_open.set_disabled(1);
-            event_open.set_exclude_kernel(1);
-            event_open.set_exclude_hv(1);
-            Ok(*event_open)
-        }
-        StatEvent::Instructions => {
-            let event_open = &mut perf_event_attr {
-                type_: perf_type_id_PERF_TYPE_HARDWARE,
-                size: PERF_EVENT_ATTR_SIZE,
-                config: perf_hw_id_PERF_COUNT_HW_INSTRUCTIONS as u64,
-                ..Default::default()
-            };
-            event_open.set_disabled(1);
-            event_open.set_exclude_kernel(1);
-            event_open.set_exclude_hv(1);
-            Ok(*event_open)
-        }
-        StatEvent::TaskClock => {
-            let event_open = &mut perf_event_attr {
-                type_: perf_type_id_PERF_TYPE_SOFTWARE,
-                size: std::mem::size_of::<perf_event_attr>() as u32,
-                config: perf_sw_ids_PERF_COUNT_SW_TASK_CLOCK as u64,
-                ..Default::default()
-            };
-            event_open.set_disabled(1);
-            event_open.set_exclude_kernel(1);
-            event_open.set_exclude_hv(1);
-            Ok(*event_open)
-        }
-        StatEvent::ContextSwitches => {
-            let event_open = &mut perf_event_attr {
-                type_: perf_type_id_PERF_TYPE_SOFTWARE,
-                size: std::mem::size_of::<perf_event_attr>() as u32,
-                config: perf_sw_ids_PERF_COUNT_SW_CONTEXT_SWITCHES as u64,
-                ..Default::default()
-            };
-            event_open.set_disabled(1);
-            event_open.set_exclude_kernel(0);
-            event_open.set_exclude_hv(1);
-            Ok(*event_open)
-        }
-        StatEvent::L1DCacheRead => {
-            let config: u64 = cache_config(
-                perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1D,
-                perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_READ,
-                perf_hw_cache_op_result_id_PERF_COUNT_HW_CACHE_RESULT_ACCESS,
-            );
-            let event_open = &mut perf_event_attr {
-                type_: perf_type_id_PERF_TYPE_HW_CACHE,
-                size: PERF_EVENT_ATTR_SIZE,
-                config,
-                ..Default::default()
-            };
-            event_open.set_disabled(1);
-            event_open.set_exclude_kernel(1);
-            event_open.set_exclude_hv(1);
-            Ok(*event_open)
-        }
-        StatEvent::L1DCacheWrite => {
-            let config: u64 = cache_config(
-                perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1D,
-                perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_WRITE,
-                perf_hw_cache_op_result_id_PERF_COUNT_HW_CACHE_RESULT_ACCESS,
-            );
-            let event_open = &mut perf_event_attr {
-                type_: perf_type_id_PERF_TYPE_HW_CACHE,
-                size: PERF_EVENT_ATTR_SIZE,
-                config,
-                ..Default::default()
-            };
-            event_open.set_disabled(1);
-            event_open.set_exclude_kernel(1);
-            event_open.set_exclude_hv(1);
-            Ok(*event_open)
-        }
-        StatEvent::L1DCacheReadMiss => {
-            let config: u64 = cache_config(
-                perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1D,
-                perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_READ,
-                perf_hw_cache_op_result_id_PERF_COUNT_HW_CACHE_RESULT_MISS,
-            );
-            let event_open = &mut perf_event_attr {
-                type_: perf_type_id_PERF_TYPE_HW_CACHE,
-                size: PERF_EVENT_ATTR_SIZE,
-                config,
-                ..Default::default()
-            };
-            event_open.set_disabled(1);
-            event_open.set_exclude_kernel(1);
-            event_open.set_exclude_hv(1);
-            Ok(*event_open)
-        }
-        StatEvent::L1ICacheReadMiss => {
-            let config: u64 = cache_config(
-                perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1I,
-                perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_READ,
-                perf_hw_cache_op_result_id_PERF_COUNT_HW_CACHE_RESULT_MISS,
-            );
-            let event_open = &mut perf_event_attr {
-                type_: perf_type_id_PERF_TYPE_HW_CACHE,
-                size: PERF_EVENT_ATTR_SIZE,
-                config,
-                ..Default::default()
-            };
-            event_open.set_disabled(1);
-            event_open.set_exclude_kernel(1);
-            event_open.set_exclude_hv(1);
-            Ok(*event_open)
+    let attr = &mut perf_event_attr {
+        type_: event.type_,
+        size: PERF_EVENT_ATTR_SIZE,
+        config: event.config,
+        __bindgen_anon_3: perf_event_attr__bindgen_ty_3 {
+            config1: event.config1,
+        },
+        __bindgen_anon_4: perf_event_attr__bindgen_ty_4 {
+            config2: event.config2,
+        },
+        ..Default::default()
+    };
+    attr.set_disabled(1);
+    let m = event.effective_modifiers();
+    attr.set_exclude_user(!m.user as u64);
+    attr.set_exclude_kernel(!m.kernel as u64);
+    attr.set_exclude_hv(!m.hv as u64);
+    attr.set_exclude_idle(m.exclude_idle as u64);
+    attr.set_exclude_host(m.guest as u64);
+    attr.set_exclude_guest(m.host as u64);
+    attr.set_precise_ip(m.precise as u64);
+    // Report how long each event was enabled and actually
+    // running, so counts can be scaled if the PMU had to
+    // multiplex more events than it has counters.
+    attr.read_format = TIMES_READ_FORMAT;
+    Ok(*attr)
+}
+
+/// True if `event` can be opened on this machine, counting
+/// this process or, failing that, everything on `cpu`.
+pub fn probe(event: &StatEvent, cpu: i32) -> bool {
+    let attr = &mut match event_open(event) {
+        Ok(attr) => attr,
+        Err(_) => return false,
+    };
+    [(Some(0), -1), (Some(-1), cpu)]
+        .iter()
+        .any(|&(pid, cpu)| fd::FileDesc::new(attr, pid, cpu, -1).is_ok())
+}
+
+/// How often a sampling event should overflow.
+#[derive(Debug, Copy, Clone)]
+pub enum SamplePeriod {
+    /// Sample every `n` occurrences of the event.
+    Period(u64),
+    /// Let the kernel adjust the period to
+    /// take roughly `n` samples per second.
+    Frequency(u64),
+}
+
+/// Initialize perf attributes for sampling `event`.
+/// `sample_type` is a mask of `PERF_SAMPLE_*` flags
+/// selecting what each sample record contains.
+/// The event also reports `mmap`, `comm` and task
+/// records so samples can be attributed afterwards.
+/// Events with the `:S` modifier add `PERF_SAMPLE_READ`,
+/// tracepoints `PERF_SAMPLE_RAW` for their fields.
+pub fn sample_open(
+    event: &StatEvent,
+    period: SamplePeriod,
+    sample_type: u64,
+) -> Result<perf_event_attr, EventErr> {
+    let mut attr = event_open(event)?;
+    match period {
+        SamplePeriod::Period(n) => attr.__bindgen_anon_1.sample_period = n,
+        SamplePeriod::Frequency(n) => {
+            attr.__bindgen_anon_1.sample_freq = n;
+            attr.set_freq(1);
         }
     }
+    attr.sample_type = sample_type;
+    if event.modifiers.sample_read {
+        attr.sample_type |= perf_event_sample_format_PERF_SAMPLE_READ;
+    }
+    if event.is_tracepoint() {
+        attr.sample_type |= perf_event_sample_format_PERF_SAMPLE_RAW;
+    }
+    attr.set_mmap(1);
+    attr.set_comm(1);
+    attr.set_task(1);
+    Ok(attr)
 }
 
 impl Event {
     /// Construct a new event.
-    pub fn new(event: StatEvent, pid: Option<i32>) -> Self {
-        let e: &mut perf_event_attr = &mut event_open(&event).unwrap();
-        let fd = fd::FileDesc::new(e, pid, -1, -1);
-        Self { fd, event }
+    pub fn new(event: StatEvent, pid: Option<i32>) -> Result<Self, EventErr> {
+        let e: &mut perf_event_attr = &mut event_open(&event)?;
+        Self::with_attr(event, e, pid, -1)
+    }
+    /// Construct an event from already initialized
+    /// attributes, e.g. those from `sample_open()`,
+    /// monitoring `pid` on `cpu`.
+    pub fn with_attr(
+        event: StatEvent,
+        attr: &mut perf_event_attr,
+        pid: Option<i32>,
+        cpu: i32,
+    ) -> Result<Self, EventErr> {
+        let fd = fd::FileDesc::new(attr, pid, cpu, -1)
+            .map_err(|e| EventErr::Open(event.to_string(), e))?;
+        Ok(Self { fd, event })
     }
     /// Start the counter on an event.
     pub fn start_counter(&self) -> Result<isize, SysErr> {
@@ -169,6 +145,12 @@ impl Event {
         }
     }
 
+    /// Map the counter's control page, to read it
+    /// from userspace without a system call.
+    pub fn user_counter(&self) -> Result<UserCounter<'_>, SysErr> {
+        UserCounter::new(&self.fd)
+    }
+
     /// Reset the counter to 0.
     pub fn reset_counter(&self) -> Result<(), SysErr> {
         match self.fd.reset() {
@@ -178,10 +160,129 @@ impl Event {
     }
 }
 
+/// Events opened as one group. The kernel only schedules
+/// a group onto the PMU as a whole, so every member counts
+/// over exactly the same interval. The first event leads.
+pub struct EventGroup {
+    pub events: Vec<Event>,
+    /// Kernel ids of `events`, to match up group reads.
+    /// `None` for inherited groups, whose members are
+    /// read one at a time.
+    ids: Option<Vec<u64>>,
+}
+
+impl EventGroup {
+    /// Open `events` as a group monitoring `pid`.
+    /// Panics if `events` is empty.
+    pub fn new(events: &[StatEvent], pid: Option<i32>) -> Result<Self, EventErr> {
+        Self::on_cpu(events, pid, -1, false)
+    }
+
+    /// Open `events` as a group monitoring `pid` on
+    /// `cpu`, or every process on `cpu` if `pid` is -1.
+    /// With `inherit` the group also counts threads and
+    /// processes `pid` starts after it was opened. Fails
+    /// with the first member that can't be opened.
+    pub fn on_cpu(
+        events: &[StatEvent],
+        pid: Option<i32>,
+        cpu: i32,
+        inherit: bool,
+    ) -> Result<Self, EventErr> {
+        assert!(!events.is_empty(), "an event group needs a leader");
+        let mut opened: Vec<Event> = Vec::new();
+        for event in events {
+            let attr = &mut event_open(event)?;
+            // Older kernels refuse group reads of inherited
+            // events, so those members are read separately.
+            if inherit {
+                attr.set_inherit(1);
+            } else {
+                attr.read_format |= (perf_event_read_format_PERF_FORMAT_GROUP
+                    | perf_event_read_format_PERF_FORMAT_ID)
+                    as u64;
+            }
+            let group_fd = match opened.first() {
+                Some(leader) => {
+                    // Members follow the leader's enabled state.
+                    attr.set_disabled(0);
+                    leader.fd.as_raw_fd()
+                }
+                None => -1,
+            };
+            let fd = fd::FileDesc::new(attr, pid, cpu, group_fd)
+                .map_err(|e| EventErr::Open(event.to_string(), e))?;
+            opened.push(Event {
+                fd,
+                event: event.clone(),
+            });
+        }
+        let ids = if inherit {
+            None
+        } else {
+            let ids: Result<Vec<u64>, EventErr> = opened
+                .iter()
+                .map(|e| {
+                    e.fd.id()
+                        .map(|id| id as u64)
+                        .map_err(|err| EventErr::Open(e.event.to_string(), err))
+                })
+                .collect();
+            Some(ids?)
+        };
+        Ok(Self {
+            events: opened,
+            ids,
+        })
+    }
+
+    fn leader(&self) -> &fd::FileDesc {
+        &self.events[0].fd
+    }
+
+    /// `err` from starting or reading the group,
+    /// naming the group by its leader.
+    pub fn count_error(&self, err: SysErr) -> EventErr {
+        EventErr::Count(self.events[0].event.to_string(), err)
+    }
+
+    /// Zero and start every counter in the group at once.
+    pub fn start(&self) -> Result<(), SysErr> {
+        self.leader().reset_group()?;
+        self.leader().enable_group()
+    }
+
+    /// Stop every counter in the group at once,
+    /// returning their counts in `events` order.
+    pub fn stop(&self) -> Result<Vec<CounterValue>, SysErr> {
+        self.leader().disable_group()?;
+        self.read()
+    }
+
+    /// Counts of every member in `events` order, taken
+    /// with a single `read()` unless the group is inherited.
+    pub fn read(&self) -> Result<Vec<CounterValue>, SysErr> {
+        let ids = match &self.ids {
+            Some(ids) => ids,
+            None => return self.events.iter().map(|e| e.fd.read_value()).collect(),
+        };
+        let values = self.leader().read_group(self.events.len())?;
+        ids.iter()
+            .map(|id| {
+                values
+                    .iter()
+                    .find(|v| v.id == *id)
+                    .copied()
+                    .ok_or(SysErr::ReadFail)
+            })
+            .collect()
+    }
+}
+
 #[cfg(test)]
 #[test]
 fn cycles_open_test() {
-    let event = Event::new(StatEvent::Cycles, None);
+    let event = Event::new(StatEvent::named("cycles"), None).unwrap();
     let cnt: isize = event.start_counter().unwrap();
     assert_ne!(cnt, 0);
     assert_ne!(cnt, -1);
@@ -192,7 +293,7 @@ fn cycles_open_test() {
 
 #[test]
 fn inst_open_test() {
-    let event = Event::new(StatEvent::Instructions, None);
+    let event = Event::new(StatEvent::named("instructions"), None).unwrap();
     let cnt: isize = event.start_counter().unwrap();
     assert_ne!(cnt, 0);
     assert_ne!(cnt, -1);
@@ -203,7 +304,7 @@ fn inst_open_test() {
 
 #[test]
 fn taskclock_open_test() {
-    let event = Event::new(StatEvent::TaskClock, None);
+    let event = Event::new(StatEvent::named("task-clock"), None).unwrap();
     let cnt: isize = event.start_counter().unwrap();
     assert_ne!(cnt, 0);
     assert_ne!(cnt, -1);
@@ -212,7 +313,7 @@ fn taskclock_open_test() {
     assert!(cnt < cnt_2);
 }
 fn l1_data_cache_read_open_test() {
-    let event = Event::new(StatEvent::L1DCacheRead, None);
+    let event = Event::new(StatEvent::named("L1D-cache-reads"), None).unwrap();
     let cnt: isize = event.start_counter().unwrap();
     assert_ne!(cnt, 0);
     assert_ne!(cnt, -1);
@@ -221,16 +322,42 @@ fn l1_data_cache_read_open_test() {
     assert!(cnt < cnt_2);
 }
 
+#[test]
+fn cpuclock_sample_test() {
+    use crate::event::sample::*;
+    let sample_type =
+        perf_event_sample_format_PERF_SAMPLE_IP | perf_event_sample_format_PERF_SAMPLE_TID;
+    let attr = &mut sample_open(
+        &StatEvent::named("cpu-clock"),
+        SamplePeriod::Period(100_000),
+        sample_type,
+    )
+    .unwrap();
+    let event = Event::with_attr(StatEvent::named("cpu-clock"), attr, None, -1).unwrap();
+    let mut ring = event.fd.mmap(8).unwrap();
+    event.start_counter().unwrap();
+    let now = std::time::Instant::now();
+    while now.elapsed().as_millis() < 20 {}
+    event.stop_counter().unwrap();
+    let samples = ring
+        .drain()
+        .iter()
+        .filter_map(|r| parse_record(r, attr.sample_type, attr.read_format))
+        .filter(|r| matches!(r, RawRecord::Sample(s) if s.ip != 0))
+        .count();
+    assert!(samples > 0, "samples = {}", samples);
+}
+
 #[test]
 fn cs_open_test() {
-    let event = Event::new(StatEvent::ContextSwitches, None);
+    let event = Event::new(StatEvent::named("context-switches"), None).unwrap();
     let cnt: isize = event.start_counter().unwrap();
     assert_ne!(cnt, -1);
     let cnt_2 = event.stop_counter().unwrap();
     assert_ne!(cnt_2, -1);
 }
 fn l1_data_cache_write_open_test() {
-    let event = Event::new(StatEvent::L1DCacheWrite, None);
+    let event = Event::new(StatEvent::named("L1D-cache-writes"), None).unwrap();
     let cnt: isize = event.start_counter().unwrap();
     assert_ne!(cnt, 0);
     assert_ne!(cnt, -1);
@@ -241,7 +368,7 @@ fn l1_data_cache_write_open_test() {
 
 #[test]
 fn l1_data_cache_read_miss_open_test() {
-    let event = Event::new(StatEvent::L1DCacheReadMiss, None);
+    let event = Event::new(StatEvent::named("L1D-cache-read-misses"), None).unwrap();
     let cnt: isize = event.start_counter().unwrap();
     assert_ne!(cnt, 0);
     assert_ne!(cnt, -1);
@@ -252,7 +379,7 @@ fn l1_data_cache_read_miss_open_test() {
 
 #[test]
 fn l1_inst_cache_read_miss_open_test() {
-    let event = Event::new(StatEvent::L1ICacheReadMiss, None);
+    let event = Event::new(StatEvent::named("L1I-cache-read-misses"), None).unwrap();
     let cnt: isize = event.start_counter().unwrap();
     assert_ne!(cnt, 0);
     assert_ne!(cnt, -1);
@@ -260,3 +387,38 @@ fn l1_inst_cache_read_miss_open_test() {
     assert_ne!(cnt, cnt_2);
     assert!(cnt < cnt_2);
 }
+
+#[test]
+fn software_group_test() {
+    let group = EventGroup::new(
+        &[
+            StatEvent::named("task-clock"),
+            StatEvent::named("cpu-clock"),
+        ],
+        None,
+    )
+    .unwrap();
+    group.start().unwrap();
+    let now = std::time::Instant::now();
+    while now.elapsed().as_millis() < 10 {}
+    let counts = group.stop().unwrap();
+    assert_eq!(counts.len(), 2);
+    assert!(counts[0].value > 0);
+    assert!(counts[1].value > 0);
+    assert!(counts[0].enabled > 0);
+    // Software events are never multiplexed.
+    assert_eq!(counts[0].scaled(), Some(counts[0].value));
+    // Stopped counters stay put.
+    assert_eq!(group.read().unwrap()[0], counts[0]);
+
+    // Inherited groups count threads started later.
+    let group = EventGroup::on_cpu(&[StatEvent::named("task-clock")], None, -1, true).unwrap();
+    group.start().unwrap();
+    std::thread::spawn(|| {
+        let now = std::time::Instant::now();
+        while now.elapsed().as_millis() < 20 {}
+    })
+    .join()
+    .unwrap();
+    assert!(group.stop().unwrap()[0].value >= 20_000_000);
+}
@@ -0,0 +1,217 @@
+//! A `UserCounter` reads a counter without a system call,
+//! through the `perf_event_mmap_page` control page mapped
+//! from its file descriptor.
+//!
+//! The kernel updates the page under a sequence lock: read
+//! `lock`, then `index`, `offset` and the `time_*` fields,
+//! and start over if `lock` changed meanwhile. While the
+//! counter is on the PMU, `index` names the hardware counter
+//! `rdpmc` reads, and adding it to `offset` gives the count.
+//! Events the PMU doesn't count, such as software events, or
+//! machines without `rdpmc` fall back to `read()`.
+//! See the `perf_event_open()` man page for the protocol.
+
+extern crate libc;
+use crate::bindings::*;
+use crate::event::fd::{CounterValue, FileDesc};
+use crate::event::utils::*;
+use std::os::unix::io::AsRawFd;
+use std::ptr::{addr_of, read_volatile};
+use std::sync::atomic::{compiler_fence, Ordering};
+
+/// `capabilities` bits of the control page.
+const CAP_USER_RDPMC: u64 = 1 << 2;
+const CAP_USER_TIME: u64 = 1 << 3;
+const CAP_USER_TIME_SHORT: u64 = 1 << 5;
+
+/// The control page of a counter, mapped for reading
+/// it from userspace. Unmapped when dropped.
+#[derive(Debug)]
+pub struct UserCounter<'a> {
+    fd: &'a FileDesc,
+    page: *mut perf_event_mmap_page,
+    len: usize,
+}
+
+/// One consistent snapshot of the control page.
+struct Snapshot {
+    /// Hardware counter to `rdpmc`, plus one. 0 if none.
+    index: u32,
+    count: u64,
+    enabled: u64,
+    running: u64,
+    /// Time since the times above were updated, if known.
+    delta: Option<u64>,
+}
+
+impl<'a> UserCounter<'a> {
+    /// Map the control page of the counter behind `fd`.
+    pub fn new(fd: &'a FileDesc) -> Result<Self, SysErr> {
+        let len = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let page = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if page == libc::MAP_FAILED {
+            return Err(SysErr::MmapFail);
+        }
+        Ok(Self {
+            fd,
+            page: page as *mut perf_event_mmap_page,
+            len,
+        })
+    }
+
+    fn capabilities(&self) -> u64 {
+        unsafe { read_volatile(addr_of!((*self.page).__bindgen_anon_1.capabilities)) }
+    }
+
+    /// True if counts can be read with `rdpmc`
+    /// rather than falling back to `read()`.
+    pub fn can_rdpmc(&self) -> bool {
+        cfg!(target_arch = "x86_64") && self.capabilities() & CAP_USER_RDPMC != 0
+    }
+
+    /// Read the control page under its sequence lock.
+    fn snapshot(&self) -> Snapshot {
+        let page = self.page;
+        loop {
+            unsafe {
+                let seq = read_volatile(addr_of!((*page).lock));
+                compiler_fence(Ordering::SeqCst);
+                let caps = self.capabilities();
+                let enabled = read_volatile(addr_of!((*page).time_enabled));
+                let running = read_volatile(addr_of!((*page).time_running));
+                let delta = if caps & CAP_USER_TIME != 0 && caps & CAP_USER_TIME_SHORT == 0 {
+                    timestamp().map(|cycles| {
+                        let shift = read_volatile(addr_of!((*page).time_shift)) as u32;
+                        let mult = read_volatile(addr_of!((*page).time_mult)) as u128;
+                        let offset = read_volatile(addr_of!((*page).time_offset));
+                        let ns = (cycles as u128 * mult) >> shift;
+                        offset.wrapping_add(ns as u64)
+                    })
+                } else {
+                    None
+                };
+                let index = read_volatile(addr_of!((*page).index));
+                let mut count = read_volatile(addr_of!((*page).offset)) as u64;
+                if index != 0 && self.can_rdpmc() {
+                    let width = read_volatile(addr_of!((*page).pmc_width)) as u32;
+                    // The counter is `width` bits wide, sign extend it.
+                    let shift = 64 - width.clamp(1, 64);
+                    let pmc = (rdpmc(index - 1) << shift) as i64 >> shift;
+                    count = count.wrapping_add(pmc as u64);
+                }
+                compiler_fence(Ordering::SeqCst);
+                if read_volatile(addr_of!((*page).lock)) == seq {
+                    return Snapshot {
+                        index,
+                        count,
+                        enabled,
+                        running,
+                        delta,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Read the count along with the times it was enabled
+    /// and running, with `rdpmc` when possible and `read()`
+    /// otherwise.
+    pub fn read(&self) -> Result<CounterValue, SysErr> {
+        if !self.can_rdpmc() {
+            return self.fd.read_value();
+        }
+        let snapshot = self.snapshot();
+        // Off the PMU or multiplexed without the time to
+        // bring the times up to date, only the kernel knows.
+        let stale = snapshot.enabled != snapshot.running && snapshot.delta.is_none();
+        if snapshot.index == 0 || stale {
+            return self.fd.read_value();
+        }
+        let delta = snapshot.delta.unwrap_or(0);
+        Ok(CounterValue {
+            value: snapshot.count,
+            enabled: snapshot.enabled + delta,
+            running: snapshot.running + delta,
+            ..Default::default()
+        })
+    }
+}
+
+impl Drop for UserCounter<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.page as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// Read hardware counter `index` of this CPU.
+#[cfg(target_arch = "x86_64")]
+fn rdpmc(index: u32) -> u64 {
+    let (low, high): (u32, u32);
+    unsafe {
+        std::arch::asm!(
+            "rdpmc",
+            in("ecx") index,
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack)
+        );
+    }
+    (high as u64) << 32 | low as u64
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn rdpmc(_index: u32) -> u64 {
+    unreachable!("rdpmc is only used on x86_64")
+}
+
+/// The time stamp counter the `time_*` fields convert.
+#[cfg(target_arch = "x86_64")]
+fn timestamp() -> Option<u64> {
+    Some(unsafe { std::arch::x86_64::_rdtsc() })
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn timestamp() -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+#[test]
+fn user_counter_test() {
+    use crate::event::open::Event;
+    use crate::stat::StatEvent;
+
+    // Software events aren't on the PMU, so they are read().
+    let event = Event::new(StatEvent::named("task-clock"), None).unwrap();
+    let counter = event.user_counter().unwrap();
+    assert!(!counter.can_rdpmc());
+    event.start_counter().unwrap();
+    let now = std::time::Instant::now();
+    while now.elapsed().as_millis() < 5 {}
+    let first = counter.read().unwrap();
+    let second = counter.read().unwrap();
+    assert!(first.value > 0);
+    assert!(second.value >= first.value);
+    event.stop_counter().unwrap();
+
+    // Hardware counters are read with rdpmc where allowed.
+    if let Ok(event) = Event::new(StatEvent::named("instructions"), None) {
+        let counter = event.user_counter().unwrap();
+        event.start_counter().unwrap();
+        let first = counter.read().unwrap();
+        let second = counter.read().unwrap();
+        assert!(second.value > first.value);
+        assert!(second.enabled >= first.enabled);
+    }
+}
@@ -0,0 +1,185 @@
+//! A `RingBuffer` maps the sampling buffer
+//! of a `perf_event` file descriptor into memory.
+//!
+//! The first page of the mapping is the
+//! `perf_event_mmap_page` control page, followed
+//! by a power of two number of data pages
+//! the kernel writes `PERF_RECORD_*` records into.
+//! See the `perf_event_open()` man page section
+//! "Reading results" for details on the protocol.
+
+extern crate libc;
+use crate::bindings::*;
+use crate::event::fd::FileDesc;
+use crate::event::utils::*;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Size in bytes of a `perf_event_header`.
+const HEADER_SIZE: usize = std::mem::size_of::<perf_event_header>();
+
+/// A memory mapped perf ring buffer.
+/// The mapping is released when dropped, and
+/// can't outlive the descriptor it came from.
+#[derive(Debug)]
+pub struct RingBuffer<'a> {
+    fd: &'a FileDesc,
+    base: *mut u8,
+    len: usize,
+    data_offset: usize,
+    data_size: usize,
+}
+
+impl<'a> RingBuffer<'a> {
+    /// Map one control page and `pages`
+    /// data pages for the event behind `fd`.
+    /// `pages` must be a power of two.
+    pub fn new(fd: &'a FileDesc, pages: usize) -> Result<Self, SysErr> {
+        if pages == 0 || !pages.is_power_of_two() {
+            return Err(SysErr::MmapArg);
+        }
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = (pages + 1) * page_size;
+        let base = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(SysErr::MmapFail);
+        }
+        let mut ring = Self {
+            fd,
+            base: base as *mut u8,
+            len,
+            data_offset: page_size,
+            data_size: pages * page_size,
+        };
+        // Kernels since 4.1 advertise where the
+        // data area starts, older ones leave it zeroed.
+        let (offset, size) = unsafe {
+            let page = ring.page();
+            (
+                std::ptr::read_volatile(std::ptr::addr_of!((*page).data_offset)),
+                std::ptr::read_volatile(std::ptr::addr_of!((*page).data_size)),
+            )
+        };
+        if offset != 0 && size != 0 {
+            ring.data_offset = offset as usize;
+            ring.data_size = size as usize;
+        }
+        Ok(ring)
+    }
+
+    /// File descriptor this buffer was mapped from.
+    pub fn fd(&self) -> &'a FileDesc {
+        self.fd
+    }
+
+    /// Size in bytes of the data area.
+    pub fn data_size(&self) -> usize {
+        self.data_size
+    }
+
+    /// The `perf_event_mmap_page` control page.
+    /// The kernel updates it concurrently, so
+    /// fields must be read through volatile
+    /// or atomic accesses.
+    pub fn page(&self) -> *mut perf_event_mmap_page {
+        self.base as *mut perf_event_mmap_page
+    }
+
+    fn data_head(&self) -> &AtomicU64 {
+        unsafe { &*(std::ptr::addr_of!((*self.page()).data_head) as *const AtomicU64) }
+    }
+
+    fn data_tail(&self) -> &AtomicU64 {
+        unsafe { &*(std::ptr::addr_of!((*self.page()).data_tail) as *const AtomicU64) }
+    }
+
+    /// Copy `buf.len()` bytes starting at
+    /// data offset `pos`, wrapping around
+    /// the end of the data area.
+    fn copy_out(&self, pos: u64, buf: &mut [u8]) {
+        let start = (pos % self.data_size as u64) as usize;
+        let first = buf.len().min(self.data_size - start);
+        unsafe {
+            let data = self.base.add(self.data_offset);
+            std::ptr::copy_nonoverlapping(data.add(start), buf.as_mut_ptr(), first);
+            std::ptr::copy_nonoverlapping(data, buf[first..].as_mut_ptr(), buf.len() - first);
+        }
+    }
+
+    /// Pop the oldest record out of the buffer,
+    /// header included. Returns `None` once the
+    /// buffer has been drained.
+    pub fn next_record(&mut self) -> Option<Vec<u8>> {
+        // Acquire pairs with the kernel's write barrier
+        // so the record body is visible once `data_head` is.
+        let head = self.data_head().load(Ordering::Acquire);
+        let tail = self.data_tail().load(Ordering::Relaxed);
+        if tail >= head {
+            return None;
+        }
+        let mut header = [0_u8; HEADER_SIZE];
+        self.copy_out(tail, &mut header);
+        let size = u16::from_ne_bytes([header[6], header[7]]) as usize;
+        if size < HEADER_SIZE || (head - tail) < size as u64 {
+            // A torn record can't be decoded;
+            // throw away what the kernel gave us.
+            self.data_tail().store(head, Ordering::Release);
+            return None;
+        }
+        let mut record = vec![0_u8; size];
+        self.copy_out(tail, &mut record);
+        // Release so the kernel doesn't overwrite
+        // the record before we're done copying it.
+        self.data_tail()
+            .store(tail + size as u64, Ordering::Release);
+        Some(record)
+    }
+
+    /// Pop every record currently in the buffer.
+    pub fn drain(&mut self) -> Vec<Vec<u8>> {
+        let mut records = Vec::new();
+        while let Some(record) = self.next_record() {
+            records.push(record);
+        }
+        records
+    }
+}
+
+impl Drop for RingBuffer<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// Wait up to `timeout` milliseconds for
+/// any of `buffers` to have data ready.
+/// Returns true if at least one is readable.
+pub fn poll(buffers: &[RingBuffer<'_>], timeout: i32) -> Result<bool, SysErr> {
+    let mut fds: Vec<libc::pollfd> = buffers
+        .iter()
+        .map(|b| libc::pollfd {
+            fd: b.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+    if ret == -1 {
+        if std::io::Error::last_os_error().raw_os_error() == Some(libc::EINTR) {
+            return Ok(false);
+        }
+        return Err(SysErr::PollFail);
+    }
+    Ok(ret > 0)
+}
@@ -0,0 +1,333 @@
+//! Decoding of the `PERF_RECORD_*` records
+//! a `RingBuffer` hands back. The layout of
+//! a `PERF_RECORD_SAMPLE` depends on the
+//! `sample_type` the event was opened with,
+//! fields appear in the order documented in
+//! the `perf_event_open()` man page.
+
+use crate::bindings::*;
+use crate::event::fd::{decode, read_len, CounterValue};
+
+/// Callchain entries at or above this value are
+/// `PERF_CONTEXT_*` markers rather than addresses.
+pub const PERF_CONTEXT_MAX: u64 = -4095_i64 as u64;
+/// Following callchain entries are kernel addresses.
+pub const PERF_CONTEXT_KERNEL: u64 = -128_i64 as u64;
+/// Following callchain entries are user addresses.
+pub const PERF_CONTEXT_USER: u64 = -512_i64 as u64;
+
+/// A decoded `PERF_RECORD_SAMPLE`. Fields that
+/// were not requested in `sample_type` are zero.
+#[derive(Debug, Default, Clone)]
+pub struct Sample {
+    pub misc: u16,
+    pub ip: u64,
+    pub pid: u32,
+    pub tid: u32,
+    pub time: u64,
+    pub addr: u64,
+    pub id: u64,
+    pub cpu: u32,
+    pub period: u64,
+    /// The counters' values, from events opened with `:S`.
+    /// One per group member with `PERF_FORMAT_GROUP`.
+    pub read: Vec<CounterValue>,
+    pub callchain: Vec<u64>,
+    pub raw: Vec<u8>,
+}
+
+/// Records produced by the kernel that
+/// `ruperf` knows how to interpret.
+#[derive(Debug, Clone)]
+pub enum RawRecord {
+    Sample(Sample),
+    Mmap {
+        pid: u32,
+        tid: u32,
+        addr: u64,
+        len: u64,
+        pgoff: u64,
+        filename: String,
+    },
+    Comm {
+        pid: u32,
+        tid: u32,
+        comm: String,
+        exec: bool,
+    },
+    Exit {
+        pid: u32,
+        ppid: u32,
+        tid: u32,
+        time: u64,
+    },
+    Fork {
+        pid: u32,
+        ppid: u32,
+        tid: u32,
+        time: u64,
+    },
+    Lost {
+        id: u64,
+        lost: u64,
+    },
+    Throttle {
+        time: u64,
+        id: u64,
+    },
+    /// Any record type not decoded above.
+    Other(u32),
+}
+
+/// Sequential native-endian reader over a record body.
+#[derive(Copy, Clone)]
+struct Cursor<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_ne_bytes([bytes[0], bytes[1]]))
+    }
+    fn u32(&mut self) -> Option<u32> {
+        let mut out = [0_u8; 4];
+        out.copy_from_slice(self.bytes(4)?);
+        Some(u32::from_ne_bytes(out))
+    }
+    fn u64(&mut self) -> Option<u64> {
+        let mut out = [0_u8; 8];
+        out.copy_from_slice(self.bytes(8)?);
+        Some(u64::from_ne_bytes(out))
+    }
+    fn u64s(&mut self, n: usize) -> Option<Vec<u64>> {
+        (0..n).map(|_| self.u64()).collect()
+    }
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let slice = self.buf.get(self.pos..self.pos + n)?;
+        self.pos += n;
+        Some(slice)
+    }
+    /// NUL terminated, u64 padded string.
+    fn string(&mut self) -> Option<String> {
+        let rest = self.buf.get(self.pos..)?;
+        let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        let s = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.pos += (len + 8) & !7;
+        Some(s)
+    }
+}
+
+/// Decode one record, header included, as copied out
+/// of a ring buffer. `sample_type` and `read_format`
+/// must match the values the event was opened with.
+/// Returns `None` for truncated records or sample
+/// types that carry fields we don't decode.
+#[allow(non_upper_case_globals)]
+pub fn parse_record(record: &[u8], sample_type: u64, read_format: u64) -> Option<RawRecord> {
+    let mut c = Cursor {
+        buf: record,
+        pos: 0,
+    };
+    let type_ = c.u32()?;
+    let misc = c.u16()?;
+    let _size = c.u16()?;
+    let parsed = match type_ {
+        perf_event_type_PERF_RECORD_SAMPLE => {
+            RawRecord::Sample(parse_sample(c, misc, sample_type, read_format)?)
+        }
+        perf_event_type_PERF_RECORD_MMAP => RawRecord::Mmap {
+            pid: c.u32()?,
+            tid: c.u32()?,
+            addr: c.u64()?,
+            len: c.u64()?,
+            pgoff: c.u64()?,
+            filename: c.string()?,
+        },
+        perf_event_type_PERF_RECORD_COMM => RawRecord::Comm {
+            pid: c.u32()?,
+            tid: c.u32()?,
+            comm: c.string()?,
+            exec: misc as u32 & PERF_RECORD_MISC_COMM_EXEC != 0,
+        },
+        perf_event_type_PERF_RECORD_EXIT => RawRecord::Exit {
+            pid: c.u32()?,
+            ppid: c.u32()?,
+            tid: c.u32()?,
+            time: {
+                c.u32()?;
+                c.u64()?
+            },
+        },
+        perf_event_type_PERF_RECORD_FORK => RawRecord::Fork {
+            pid: c.u32()?,
+            ppid: c.u32()?,
+            tid: c.u32()?,
+            time: {
+                c.u32()?;
+                c.u64()?
+            },
+        },
+        perf_event_type_PERF_RECORD_LOST => RawRecord::Lost {
+            id: c.u64()?,
+            lost: c.u64()?,
+        },
+        perf_event_type_PERF_RECORD_THROTTLE | perf_event_type_PERF_RECORD_UNTHROTTLE => {
+            RawRecord::Throttle {
+                time: c.u64()?,
+                id: c.u64()?,
+            }
+        }
+        other => RawRecord::Other(other),
+    };
+    Some(parsed)
+}
+
+/// Sample types `parse_sample` can walk past.
+const SUPPORTED_SAMPLE_TYPE: u64 = perf_event_sample_format_PERF_SAMPLE_IDENTIFIER
+    | perf_event_sample_format_PERF_SAMPLE_IP
+    | perf_event_sample_format_PERF_SAMPLE_TID
+    | perf_event_sample_format_PERF_SAMPLE_TIME
+    | perf_event_sample_format_PERF_SAMPLE_ADDR
+    | perf_event_sample_format_PERF_SAMPLE_ID
+    | perf_event_sample_format_PERF_SAMPLE_STREAM_ID
+    | perf_event_sample_format_PERF_SAMPLE_CPU
+    | perf_event_sample_format_PERF_SAMPLE_PERIOD
+    | perf_event_sample_format_PERF_SAMPLE_READ
+    | perf_event_sample_format_PERF_SAMPLE_CALLCHAIN
+    | perf_event_sample_format_PERF_SAMPLE_RAW;
+
+fn parse_sample(mut c: Cursor, misc: u16, sample_type: u64, read_format: u64) -> Option<Sample> {
+    if sample_type & !SUPPORTED_SAMPLE_TYPE != 0 {
+        return None;
+    }
+    let has = |bit: u64| sample_type & bit != 0;
+    let mut s = Sample {
+        misc,
+        ..Default::default()
+    };
+    if has(perf_event_sample_format_PERF_SAMPLE_IDENTIFIER) {
+        s.id = c.u64()?;
+    }
+    if has(perf_event_sample_format_PERF_SAMPLE_IP) {
+        s.ip = c.u64()?;
+    }
+    if has(perf_event_sample_format_PERF_SAMPLE_TID) {
+        s.pid = c.u32()?;
+        s.tid = c.u32()?;
+    }
+    if has(perf_event_sample_format_PERF_SAMPLE_TIME) {
+        s.time = c.u64()?;
+    }
+    if has(perf_event_sample_format_PERF_SAMPLE_ADDR) {
+        s.addr = c.u64()?;
+    }
+    if has(perf_event_sample_format_PERF_SAMPLE_ID) {
+        s.id = c.u64()?;
+    }
+    if has(perf_event_sample_format_PERF_SAMPLE_STREAM_ID) {
+        c.u64()?;
+    }
+    if has(perf_event_sample_format_PERF_SAMPLE_CPU) {
+        s.cpu = c.u32()?;
+        c.u32()?;
+    }
+    if has(perf_event_sample_format_PERF_SAMPLE_PERIOD) {
+        s.period = c.u64()?;
+    }
+    // Laid out as a read() of the counter, a group
+    // starting with the number of members.
+    if has(perf_event_sample_format_PERF_SAMPLE_READ) {
+        let members = if read_format & perf_event_read_format_PERF_FORMAT_GROUP as u64 != 0 {
+            let mut peek = c;
+            peek.u64()? as usize
+        } else {
+            1
+        };
+        s.read = decode(read_format, &c.u64s(read_len(read_format, members))?).ok()?;
+    }
+    if has(perf_event_sample_format_PERF_SAMPLE_CALLCHAIN) {
+        let nr = c.u64()?;
+        for _ in 0..nr {
+            s.callchain.push(c.u64()?);
+        }
+    }
+    if has(perf_event_sample_format_PERF_SAMPLE_RAW) {
+        let size = c.u32()? as usize;
+        s.raw = c.bytes(size)?.to_vec();
+    }
+    Some(s)
+}
+
+#[cfg(test)]
+#[test]
+fn parse_sample_test() {
+    let sample_type = perf_event_sample_format_PERF_SAMPLE_IP
+        | perf_event_sample_format_PERF_SAMPLE_TID
+        | perf_event_sample_format_PERF_SAMPLE_PERIOD
+        | perf_event_sample_format_PERF_SAMPLE_CALLCHAIN;
+    let mut record: Vec<u8> = Vec::new();
+    record.extend(&perf_event_type_PERF_RECORD_SAMPLE.to_ne_bytes());
+    record.extend(&(PERF_RECORD_MISC_USER as u16).to_ne_bytes());
+    record.extend(&56_u16.to_ne_bytes());
+    record.extend(&0x4000_u64.to_ne_bytes());
+    record.extend(&7_u32.to_ne_bytes());
+    record.extend(&8_u32.to_ne_bytes());
+    record.extend(&100_u64.to_ne_bytes());
+    record.extend(&2_u64.to_ne_bytes());
+    record.extend(&0x4000_u64.to_ne_bytes());
+    record.extend(&0x5000_u64.to_ne_bytes());
+    match parse_record(&record, sample_type, 0) {
+        Some(RawRecord::Sample(s)) => {
+            assert_eq!(s.ip, 0x4000);
+            assert_eq!((s.pid, s.tid), (7, 8));
+            assert_eq!(s.period, 100);
+            assert_eq!(s.callchain, vec![0x4000, 0x5000]);
+        }
+        other => panic!("unexpected record {:?}", other),
+    }
+
+    // A group read with times and ids: { nr, enabled,
+    // running, { value, id } * nr }.
+    let read_format = (perf_event_read_format_PERF_FORMAT_GROUP
+        | perf_event_read_format_PERF_FORMAT_TOTAL_TIME_ENABLED
+        | perf_event_read_format_PERF_FORMAT_TOTAL_TIME_RUNNING
+        | perf_event_read_format_PERF_FORMAT_ID) as u64;
+    let mut record: Vec<u8> = Vec::new();
+    record.extend(&perf_event_type_PERF_RECORD_SAMPLE.to_ne_bytes());
+    record.extend(&(PERF_RECORD_MISC_USER as u16).to_ne_bytes());
+    record.extend(&64_u16.to_ne_bytes());
+    for field in [2_u64, 1000, 500, 10, 1, 20, 2] {
+        record.extend(&field.to_ne_bytes());
+    }
+    let sample_type = perf_event_sample_format_PERF_SAMPLE_READ;
+    match parse_record(&record, sample_type, read_format) {
+        Some(RawRecord::Sample(s)) => {
+            assert_eq!(s.read.len(), 2);
+            assert_eq!((s.read[1].value, s.read[1].id), (20, 2));
+            assert_eq!((s.read[1].enabled, s.read[1].running), (1000, 500));
+        }
+        other => panic!("unexpected record {:?}", other),
+    }
+    // Cut short, the group doesn't decode.
+    assert!(parse_record(&record[..56], sample_type, read_format).is_none());
+}
+
+#[test]
+fn parse_comm_test() {
+    let mut record: Vec<u8> = Vec::new();
+    record.extend(&perf_event_type_PERF_RECORD_COMM.to_ne_bytes());
+    record.extend(&(PERF_RECORD_MISC_COMM_EXEC as u16).to_ne_bytes());
+    record.extend(&24_u16.to_ne_bytes());
+    record.extend(&1_u32.to_ne_bytes());
+    record.extend(&1_u32.to_ne_bytes());
+    record.extend(b"true\0\0\0\0");
+    match parse_record(&record, 0, 0) {
+        Some(RawRecord::Comm { comm, exec, .. }) => {
+            assert_eq!(comm, "true");
+            assert!(exec);
+        }
+        other => panic!("unexpected record {:?}", other),
+    }
+}
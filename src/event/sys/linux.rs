@@ -55,12 +55,9 @@ pub const REFRESH: u32 = iocn(2);
 pub const RESET: u32 = iocn(3);
 pub const PERIOD: u32 = iocw(4, size_of::<u64>());
 pub const SET_OUTPUT: u32 = iocn(5);
-/** NOT SUPPORTED **/
 pub const SET_FILTER: u32 = iocw(6, size_of::<*const char>());
 pub const ID: u32 = iocr(7, size_of::<*const u64>());
-/** NOT SUPPORTED **/
 pub const SET_BPF: u32 = iocw(8, size_of::<u32>());
 pub const PAUSE_OUTPUT: u32 = iocw(9, size_of::<u32>());
-/** NOT SUPPORTED **/
 pub const QUERY_BPF: u32 = iocwr(10, size_of::<*const perf_event_query_bpf>());
-pub const MODIFY_ATTRIBUTES: u32 = iocwr(11, size_of::<*const perf_event_attr>());
+pub const MODIFY_ATTRIBUTES: u32 = iocw(11, size_of::<*const perf_event_attr>());
@@ -3,13 +3,26 @@
 //! `read()` see the Linux man-page.
 
 extern crate libc;
+use crate::event::utils::SysErr;
 use libc::read;
 
-pub fn read_wrap(fd: i32) -> isize {
-    let mut count: isize = 0;
-    unsafe {
-        let buf: *mut libc::c_void = &mut count as *mut _ as *mut libc::c_void;
-        read(fd, buf, std::mem::size_of_val(&count));
+/// Read as many `u64`s as fit in `buf`,
+/// returning how many bytes were read.
+/// A signal arriving first is an error,
+/// the caller decides whether to retry.
+pub fn read_buf(fd: i32, buf: &mut [u64]) -> Result<usize, SysErr> {
+    let ret = unsafe {
+        read(
+            fd,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            std::mem::size_of_val(buf),
+        )
+    };
+    if ret == -1 {
+        return Err(match std::io::Error::last_os_error().raw_os_error() {
+            Some(libc::EINTR) => SysErr::Interrupted,
+            _ => SysErr::ReadFail,
+        });
     }
-    count
+    Ok(ret as usize)
 }
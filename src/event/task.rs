@@ -0,0 +1,53 @@
+//! Helpers for finding the tasks events can be
+//! attached to. A per-task event only counts the
+//! thread it was opened on, so attaching to a
+//! process means opening one event per thread.
+
+use std::fs;
+
+/// Name of the command `pid` is running.
+pub fn read_comm(pid: u32) -> String {
+    fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map(|s| s.trim_end().to_string())
+        .unwrap_or_else(|_| format!(":{}", pid))
+}
+
+/// Threads of `pid`, or just `pid` if
+/// `/proc/<pid>/task` can't be listed.
+pub fn threads_of(pid: i32) -> Vec<i32> {
+    let mut tids: Vec<i32> = fs::read_dir(format!("/proc/{}/task", pid))
+        .map(|dir| {
+            dir.filter_map(|e| e.ok()?.file_name().to_str()?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    if tids.is_empty() {
+        tids.push(pid);
+    }
+    tids.sort_unstable();
+    tids
+}
+
+/// True if task `tid` exists and hasn't exited. Zombies
+/// waiting for a parent that isn't us count as exited.
+pub fn is_running(tid: i32) -> bool {
+    let stat = match fs::read_to_string(format!("/proc/{}/stat", tid)) {
+        Ok(stat) => stat,
+        Err(_) => return false,
+    };
+    // The state follows the command, which may contain spaces.
+    let state = stat
+        .rfind(')')
+        .and_then(|i| stat[i + 1..].trim_start().chars().next());
+    !matches!(state, None | Some('Z') | Some('X'))
+}
+
+#[cfg(test)]
+#[test]
+fn threads_of_self_test() {
+    let pid = std::process::id() as i32;
+    assert!(threads_of(pid).contains(&pid));
+    assert!(is_running(pid));
+    assert!(!is_running(i32::MAX));
+    assert!(!read_comm(pid as u32).is_empty());
+}
@@ -0,0 +1,239 @@
+//! Tracepoints, as described by tracefs. Each
+//! `events/<subsys>/<event>` directory holds the
+//! `id` to open the tracepoint with and a `format`
+//! file laying out the raw data of its samples.
+
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where tracefs is mounted, newest location first.
+/// Older kernels only expose it through debugfs.
+pub const TRACEFS_ROOTS: &[&str] = &["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// The directory of tracepoint `subsys:event` under
+/// the first of `roots` that has it.
+pub fn find_tracepoint(roots: &[&str], subsys: &str, event: &str) -> Option<PathBuf> {
+    if subsys.contains('/') || event.contains('/') {
+        return None;
+    }
+    roots
+        .iter()
+        .map(|root| Path::new(root).join("events").join(subsys).join(event))
+        .find(|dir| dir.join("id").is_file())
+}
+
+/// The id to pass as `config` for the tracepoint in `dir`.
+pub fn tracepoint_id(dir: &Path) -> Option<u64> {
+    fs::read_to_string(dir.join("id")).ok()?.trim().parse().ok()
+}
+
+/// One field of a tracepoint's raw data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub name: String,
+    /// C declaration without the name, e.g. `char[16]`.
+    pub type_: String,
+    pub offset: usize,
+    pub size: usize,
+    pub signed: bool,
+}
+
+impl Field {
+    /// `__data_loc` fields hold the offset and length of
+    /// data stored after the fixed fields, `__rel_loc`
+    /// ones the same relative to the end of the field.
+    fn is_dynamic(&self) -> bool {
+        self.type_.starts_with("__data_loc") || self.type_.starts_with("__rel_loc")
+    }
+
+    fn is_string(&self) -> bool {
+        self.type_.contains("char[") || self.type_.ends_with("char[]")
+    }
+}
+
+/// A decoded field value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum TraceValue {
+    Signed(i64),
+    Unsigned(u64),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+/// A named field of a tracepoint sample.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceField {
+    pub name: String,
+    pub value: TraceValue,
+}
+
+/// The layout of a tracepoint's raw data, from its `format` file.
+#[derive(Debug, Clone, Default)]
+pub struct Format {
+    pub fields: Vec<Field>,
+}
+
+/// Parse a line such as
+/// `field:char prev_comm[16]; offset:8; size:16; signed:0;`.
+fn parse_field(line: &str) -> Option<Field> {
+    let mut decl = None;
+    let mut offset = None;
+    let mut size = None;
+    let mut signed = false;
+    for part in line.split(';').map(str::trim) {
+        match part.split_once(':') {
+            Some(("field", d)) => decl = Some(d.trim()),
+            Some(("offset", n)) => offset = n.trim().parse().ok(),
+            Some(("size", n)) => size = n.trim().parse().ok(),
+            Some(("signed", n)) => signed = n.trim() == "1",
+            _ => {}
+        }
+    }
+    // The name is the last word, less any array bounds
+    // which belong with the type: `char prev_comm[16]`.
+    let decl = decl?;
+    let (type_, name) = decl.rsplit_once(' ')?;
+    let (name, bounds) = match name.find('[') {
+        Some(i) => name.split_at(i),
+        None => (name, ""),
+    };
+    Some(Field {
+        name: name.to_string(),
+        type_: format!("{}{}", type_.trim(), bounds),
+        offset: offset?,
+        size: size?,
+        signed,
+    })
+}
+
+impl Format {
+    /// Parse the contents of a `format` file.
+    pub fn parse(text: &str) -> Self {
+        let fields = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| l.starts_with("field:"))
+            .filter_map(parse_field)
+            .collect();
+        Self { fields }
+    }
+
+    /// Read the `format` file of the tracepoint in `dir`.
+    pub fn read(dir: &Path) -> Option<Self> {
+        fs::read_to_string(dir.join("format"))
+            .ok()
+            .map(|text| Self::parse(&text))
+    }
+
+    /// Decode `raw`, a sample's `PERF_SAMPLE_RAW` data, into
+    /// named fields. The `common_*` fields every tracepoint
+    /// starts with are left out, fields past the end of a
+    /// truncated record are skipped.
+    pub fn decode(&self, raw: &[u8]) -> Vec<TraceField> {
+        self.fields
+            .iter()
+            .filter(|f| !f.name.starts_with("common_"))
+            .filter_map(|f| {
+                let value = decode_field(f, raw)?;
+                Some(TraceField {
+                    name: f.name.clone(),
+                    value,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Native endian integer of up to 8 bytes.
+fn read_int(bytes: &[u8], signed: bool) -> TraceValue {
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    let value = u64::from_ne_bytes(buf);
+    if !signed {
+        return TraceValue::Unsigned(value);
+    }
+    let shift = 64 - 8 * bytes.len() as u32;
+    TraceValue::Signed(((value << shift) as i64) >> shift)
+}
+
+/// Text up to the first NUL.
+fn read_string(bytes: &[u8]) -> TraceValue {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    TraceValue::Str(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+fn decode_field(field: &Field, raw: &[u8]) -> Option<TraceValue> {
+    let bytes = raw.get(field.offset..field.offset + field.size)?;
+    let bytes = if field.is_dynamic() {
+        let loc = u32::from_ne_bytes(bytes.try_into().ok()?);
+        let mut start = (loc & 0xffff) as usize;
+        if field.type_.starts_with("__rel_loc") {
+            start += field.offset + field.size;
+        }
+        raw.get(start..start + (loc >> 16) as usize)?
+    } else {
+        bytes
+    };
+    Some(match bytes.len() {
+        _ if field.is_string() => read_string(bytes),
+        1 | 2 | 4 | 8 if !field.type_.contains('[') => read_int(bytes, field.signed),
+        _ => TraceValue::Bytes(bytes.to_vec()),
+    })
+}
+
+#[cfg(test)]
+#[test]
+fn tracepoint_format_test() {
+    let root = std::env::temp_dir().join(format!("ruperf-tracefs-{}", std::process::id()));
+    let dir = root.join("events/sched/sched_switch");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("id"), "316\n").unwrap();
+    fs::write(
+        dir.join("format"),
+        "name: sched_switch\nID: 316\nformat:\n\
+         \tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;\n\
+         \tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;\n\n\
+         \tfield:char prev_comm[16];\toffset:8;\tsize:16;\tsigned:0;\n\
+         \tfield:pid_t prev_pid;\toffset:24;\tsize:4;\tsigned:1;\n\
+         \tfield:long prev_state;\toffset:28;\tsize:8;\tsigned:1;\n\
+         \tfield:__data_loc char[] name;\toffset:36;\tsize:4;\tsigned:0;\n\n\
+         print fmt: \"prev_comm=%s\", REC->prev_comm\n",
+    )
+    .unwrap();
+
+    let roots = [root.to_str().unwrap()];
+    let found = find_tracepoint(&roots, "sched", "sched_switch").unwrap();
+    assert_eq!(tracepoint_id(&found), Some(316));
+    assert!(find_tracepoint(&roots, "sched", "nope").is_none());
+    assert!(find_tracepoint(&roots, "..", "sched/sched_switch").is_none());
+
+    let format = Format::read(&found).unwrap();
+    assert_eq!(format.fields.len(), 6);
+    assert_eq!(format.fields[2].name, "prev_comm");
+    assert_eq!(format.fields[2].type_, "char[16]");
+
+    let mut raw = vec![0u8; 40];
+    raw[8..12].copy_from_slice(b"bash");
+    raw[24..28].copy_from_slice(&(-1i32).to_ne_bytes());
+    raw[28..36].copy_from_slice(&2i64.to_ne_bytes());
+    raw[36..40].copy_from_slice(&(40u32 | 3 << 16).to_ne_bytes());
+    raw.extend_from_slice(b"ab\0");
+    let fields = format.decode(&raw);
+    let values: Vec<(&str, &TraceValue)> =
+        fields.iter().map(|f| (f.name.as_str(), &f.value)).collect();
+    assert_eq!(
+        values,
+        vec![
+            ("prev_comm", &TraceValue::Str("bash".to_string())),
+            ("prev_pid", &TraceValue::Signed(-1)),
+            ("prev_state", &TraceValue::Signed(2)),
+            ("name", &TraceValue::Str("ab".to_string())),
+        ]
+    );
+    // Truncated data decodes what it can.
+    assert_eq!(format.decode(&raw[..28]).len(), 2);
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -1,21 +1,106 @@
-//! This file may contain more items in the
-//! future. For now it defines a generic `Result`
-//! type for handling system call failures and
-//! invalid event requests.
+//! Errors from system calls and from opening
+//! events, along with a generic `Result` type
+//! for handling them.
+
+use thiserror::Error;
 
 type Result<T, E> = std::result::Result<T, E>;
 
 /// Errors related to system calls.
-#[derive(Debug)]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SysErr {
+    #[error("{}", open_hint(*.0))]
+    OpenFail(i32),
+    #[error("Failed to read counter")]
     ReadFail,
-    IoFail,
+    #[error("Short read of counter, {0} of {1} bytes")]
+    ShortRead(usize, usize),
+    #[error("Counter read interrupted by a signal")]
+    Interrupted,
+    #[error("ioctl() on counter failed ({})", strerror(*.0))]
+    IoFail(i32),
+    #[error("Invalid ioctl() argument")]
     IoArg,
+    #[error("Kernel returned no event id")]
     IoId,
+    #[error("Invalid ring buffer size, expected a power of two pages")]
+    MmapArg,
+    #[error("Failed to mmap ring buffer")]
+    MmapFail,
+    #[error("poll() on ring buffers failed")]
+    PollFail,
+}
+
+impl SysErr {
+    /// `OpenFail` with the `errno` the last
+    /// `perf_event_open()` call set.
+    pub fn last_open() -> Self {
+        SysErr::OpenFail(last_errno())
+    }
+
+    /// `IoFail` with the `errno` the last `ioctl()` set.
+    pub fn last_io() -> Self {
+        SysErr::IoFail(last_errno())
+    }
+}
+
+fn last_errno() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
+
+/// Why `perf_event_open()` may have failed with `errno`.
+fn open_hint(errno: i32) -> String {
+    let hint = match errno {
+        libc::EACCES | libc::EPERM => {
+            "not allowed, lower /proc/sys/kernel/perf_event_paranoid \
+             or run with CAP_PERFMON (CAP_SYS_ADMIN before Linux 5.8)"
+        }
+        libc::ENOENT => "this kernel or CPU doesn't have the event",
+        libc::EOPNOTSUPP => "the PMU can't count it this way, e.g. sampling or excluding modes",
+        libc::EINVAL => "invalid event settings, or a CPU or PMU this machine doesn't have",
+        libc::EMFILE => "too many open files, raise the limit with ulimit -n",
+        libc::EBUSY => "the PMU is in use by something else",
+        libc::ESRCH => "no such process or thread",
+        _ => return strerror(errno),
+    };
+    format!("{} ({})", hint, strerror(errno))
+}
+
+/// The C library's description of `errno`.
+fn strerror(errno: i32) -> String {
+    let text = unsafe { std::ffi::CStr::from_ptr(libc::strerror(errno)) };
+    text.to_string_lossy().into_owned()
 }
 
 /// Errors related to handling specific events.
-#[derive(Debug)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum EventErr {
+    #[error("Invalid event")]
     InvalidEvent,
+    #[error("Can't open '{0}': {1}")]
+    Open(String, SysErr),
+    #[error("Can't count '{0}': {1}")]
+    Count(String, SysErr),
+}
+
+#[cfg(test)]
+#[test]
+fn errors_test() {
+    let err = EventErr::Open("cycles".to_string(), SysErr::OpenFail(libc::EACCES));
+    let text = err.to_string();
+    assert!(text.starts_with("Can't open 'cycles': not allowed"));
+    assert!(text.contains("perf_event_paranoid"));
+    assert!(text.contains("CAP_PERFMON"));
+    let err = EventErr::Count("cycles".to_string(), SysErr::ShortRead(8, 24));
+    assert_eq!(
+        err.to_string(),
+        "Can't count 'cycles': Short read of counter, 8 of 24 bytes"
+    );
+    assert!(SysErr::OpenFail(libc::ENOENT)
+        .to_string()
+        .contains("doesn't have"));
+    assert_eq!(
+        SysErr::OpenFail(libc::ENXIO).to_string(),
+        "No such device or address"
+    );
 }
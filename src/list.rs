@@ -0,0 +1,405 @@
+//! # List driver.
+//! <p> Usage: <em> ruperf list [OPTION] [PATTERN]... </em>
+//! Where PATTERN is a glob such as `sched:*` or a word
+//! to search event names for. </p>
+
+extern crate structopt;
+use crate::event::cpu::online_cpus;
+use crate::event::open::probe;
+use crate::event::tracepoint::TRACEFS_ROOTS;
+use crate::stat::spec::{HARDWARE, SOFTWARE, SYSFS_PMUS};
+use crate::stat::StatEvent;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use structopt::StructOpt;
+
+/// Hardware caches, as perf spells them.
+const CACHE_NAMES: &[&str] = &[
+    "L1-dcache",
+    "L1-icache",
+    "LLC",
+    "dTLB",
+    "iTLB",
+    "branch",
+    "node",
+];
+
+/// Access and miss suffixes of each cache operation.
+const CACHE_OPS: &[(&str, &str)] = &[
+    ("loads", "load-misses"),
+    ("stores", "store-misses"),
+    ("prefetches", "prefetch-misses"),
+];
+
+/// Configuration settings for running list. See `./ruperf list --help`
+/// for more information.
+#[derive(Debug, StructOpt)]
+pub struct ListOptions {
+    #[structopt(help = "Only list events matching one of these globs or words")]
+    pub patterns: Vec<String>,
+
+    #[structopt(short, long, help = "Format output as json")]
+    pub json: bool,
+}
+
+/// What kind of event an entry is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Hardware,
+    Software,
+    Cache,
+    Tracepoint,
+    Pmu,
+}
+
+impl EventKind {
+    fn description(&self) -> &'static str {
+        match self {
+            EventKind::Hardware => "Hardware event",
+            EventKind::Software => "Software event",
+            EventKind::Cache => "Hardware cache event",
+            EventKind::Tracepoint => "Tracepoint event",
+            EventKind::Pmu => "Kernel PMU event",
+        }
+    }
+}
+
+/// One listed event.
+#[derive(Debug, Serialize)]
+pub struct ListedEvent {
+    pub name: String,
+    /// Other names for the same event.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    pub kind: EventKind,
+    /// The terms a PMU alias stands for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terms: Option<String>,
+    /// True if the event opened on this machine.
+    pub supported: bool,
+}
+
+/// A format field of a PMU, e.g. `event` is `config:0-7`.
+#[derive(Debug, Serialize)]
+pub struct PmuFormat {
+    pub name: String,
+    pub bits: String,
+}
+
+/// A dynamic PMU and the fields its events are built from.
+#[derive(Debug, Serialize)]
+pub struct Pmu {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: u32,
+    pub formats: Vec<PmuFormat>,
+}
+
+/// Everything `ruperf list` found, as printed with `--json`.
+#[derive(Debug, Serialize)]
+pub struct Listing {
+    pub events: Vec<ListedEvent>,
+    pub pmus: Vec<Pmu>,
+}
+
+/// Match `text` against a glob where `*` is any run of
+/// characters and `?` any one character.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    // Where the last `*` was, and how much of `text` it covers.
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        match p.get(pi) {
+            Some('*') => {
+                star = Some((pi, ti));
+                pi += 1;
+            }
+            Some(&c) if c == '?' || c == t[ti] => {
+                pi += 1;
+                ti += 1;
+            }
+            _ => match star {
+                Some((sp, st)) => {
+                    pi = sp + 1;
+                    ti = st + 1;
+                    star = Some((sp, st + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// True if `name` matches any of `patterns`, or there are none.
+/// Patterns without wildcards match anywhere in the name.
+fn matches(patterns: &[String], name: &str) -> bool {
+    patterns.is_empty()
+        || patterns.iter().any(|p| {
+            if p.contains(['*', '?']) {
+                glob_match(p, name)
+            } else {
+                name.contains(p.as_str())
+            }
+        })
+}
+
+/// Names in `table` grouped by the id they share.
+fn generic_events(table: &[(&str, u32)], kind: EventKind) -> Vec<(EventKind, Vec<String>)> {
+    let mut grouped: Vec<(u32, Vec<String>)> = Vec::new();
+    for (name, id) in table {
+        match grouped.iter_mut().find(|(i, _)| i == id) {
+            Some((_, names)) => names.push(name.to_string()),
+            None => grouped.push((*id, vec![name.to_string()])),
+        }
+    }
+    grouped
+        .into_iter()
+        .map(|(_, names)| (kind, names))
+        .collect()
+}
+
+fn cache_events() -> Vec<(EventKind, Vec<String>)> {
+    CACHE_NAMES
+        .iter()
+        .flat_map(|cache| {
+            CACHE_OPS.iter().flat_map(move |(access, miss)| {
+                vec![
+                    (EventKind::Cache, vec![format!("{}-{}", cache, access)]),
+                    (EventKind::Cache, vec![format!("{}-{}", cache, miss)]),
+                ]
+            })
+        })
+        .collect()
+}
+
+/// Sorted names of the directories in `dir`.
+fn subdirs(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().is_dir())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// Sorted names of the files in `dir`, and their trimmed contents.
+fn files(dir: &Path) -> Vec<(String, String)> {
+    let mut files: Vec<(String, String)> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| {
+                    let contents = fs::read_to_string(e.path()).ok()?;
+                    let name = e.file_name().to_string_lossy().into_owned();
+                    Some((name, contents.trim().to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+/// Every `subsys:event` in the first mounted tracefs.
+fn tracepoints() -> Vec<(EventKind, Vec<String>)> {
+    let events = match TRACEFS_ROOTS
+        .iter()
+        .map(|root| Path::new(root).join("events"))
+        .find(|dir| dir.is_dir())
+    {
+        Some(events) => events,
+        None => return Vec::new(),
+    };
+    subdirs(&events)
+        .into_iter()
+        .flat_map(|subsys| {
+            let dir = events.join(&subsys);
+            subdirs(&dir)
+                .into_iter()
+                .filter(|event| dir.join(event).join("id").is_file())
+                .map(|event| (EventKind::Tracepoint, vec![format!("{}:{}", subsys, event)]))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The PMUs under `root`, and the events their `events/` aliases name.
+fn pmus(root: &Path) -> (Vec<Pmu>, Vec<(String, String)>) {
+    let mut pmus = Vec::new();
+    let mut aliases = Vec::new();
+    for name in subdirs(root) {
+        let dir = root.join(&name);
+        let type_ = match fs::read_to_string(dir.join("type"))
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+        {
+            Some(type_) => type_,
+            None => continue,
+        };
+        // `<alias>.scale` and `.unit` files describe the alias.
+        for (alias, terms) in files(&dir.join("events")) {
+            if !alias.contains('.') {
+                aliases.push((format!("{}/{}/", name, alias), terms));
+            }
+        }
+        let formats = files(&dir.join("format"))
+            .into_iter()
+            .map(|(name, bits)| PmuFormat { name, bits })
+            .collect();
+        pmus.push(Pmu {
+            name,
+            type_,
+            formats,
+        });
+    }
+    (pmus, aliases)
+}
+
+/// Opens events to see which this machine supports.
+struct Prober {
+    /// CPU to try events that can't count a single process on.
+    cpu: i32,
+    /// Whether tracepoints open. Opening one takes milliseconds and
+    /// whether it works only depends on permissions, so the first
+    /// answer stands for all of them.
+    tracepoints: Option<bool>,
+}
+
+impl Prober {
+    fn supports(&mut self, name: &str) -> bool {
+        let event = match name.parse::<StatEvent>() {
+            Ok(event) => event,
+            Err(_) => return false,
+        };
+        if !event.is_tracepoint() {
+            return probe(&event, self.cpu);
+        }
+        let cpu = self.cpu;
+        *self.tracepoints.get_or_insert_with(|| probe(&event, cpu))
+    }
+
+    /// List the event named first in `names`.
+    fn listed(
+        &mut self,
+        names: Vec<String>,
+        kind: EventKind,
+        terms: Option<String>,
+    ) -> ListedEvent {
+        let supported = self.supports(&names[0]);
+        let mut names = names.into_iter();
+        ListedEvent {
+            name: names.next().unwrap(),
+            aliases: names.collect(),
+            kind,
+            terms,
+            supported,
+        }
+    }
+}
+
+/// Gather every event matching `patterns`.
+pub fn list_events(patterns: &[String]) -> Listing {
+    let mut prober = Prober {
+        cpu: online_cpus().first().copied().unwrap_or(0),
+        tracepoints: None,
+    };
+    let (pmus, aliases) = pmus(Path::new(SYSFS_PMUS));
+    let mut named = generic_events(HARDWARE, EventKind::Hardware);
+    named.extend(generic_events(SOFTWARE, EventKind::Software));
+    named.extend(cache_events());
+    named.extend(tracepoints());
+
+    let mut events: Vec<ListedEvent> = named
+        .into_iter()
+        .filter(|(_, names)| names.iter().any(|n| matches(patterns, n)))
+        .map(|(kind, names)| prober.listed(names, kind, None))
+        .collect();
+    events.extend(
+        aliases
+            .into_iter()
+            .filter(|(name, _)| matches(patterns, name))
+            .map(|(name, terms)| prober.listed(vec![name], EventKind::Pmu, Some(terms))),
+    );
+    let pmus = pmus
+        .into_iter()
+        .filter(|pmu| !pmu.formats.is_empty())
+        .filter(|pmu| patterns.is_empty() || matches(patterns, &format!("{}/", pmu.name)))
+        .collect();
+    Listing { events, pmus }
+}
+
+/// Run perf list.
+pub fn run_list(options: ListOptions) {
+    let listing = list_events(&options.patterns);
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&listing).unwrap());
+        return;
+    }
+
+    println!("\nList of pre-defined events (to be used in -e):\n");
+    for event in &listing.events {
+        let mut names = event.name.clone();
+        for alias in &event.aliases {
+            names = format!("{} OR {}", names, alias);
+        }
+        if let Some(terms) = &event.terms {
+            names = format!("{} ({})", names, terms);
+        }
+        let supported = if event.supported {
+            ""
+        } else {
+            ", not supported"
+        };
+        println!(
+            "  {:<50} [{}{}]",
+            names,
+            event.kind.description(),
+            supported
+        );
+    }
+    if !listing.pmus.is_empty() {
+        println!("\nPMU formats (to be used as pmu/field=value,.../):\n");
+        for pmu in &listing.pmus {
+            let formats: Vec<String> = pmu
+                .formats
+                .iter()
+                .map(|f| format!("{}={}", f.name, f.bits))
+                .collect();
+            println!("  {:<20} {}", format!("{}/", pmu.name), formats.join(" "));
+        }
+    }
+    println!();
+}
+
+#[cfg(test)]
+#[test]
+fn list_test() {
+    assert!(glob_match("sched:*", "sched:sched_switch"));
+    assert!(glob_match("*cache*miss?s", "L1-dcache-load-misses"));
+    assert!(glob_match("a*b*c", "aXbYbZc"));
+    assert!(!glob_match("sched:*", "syscalls:sys_enter_openat"));
+    assert!(!glob_match("cycles?", "cycles"));
+    assert!(matches(&["clock".to_string()], "task-clock"));
+    assert!(matches(&[], "anything"));
+
+    let listing = list_events(&["cpu-clock".to_string(), "L1-dcache-*".to_string()]);
+    let names: Vec<&str> = listing.events.iter().map(|e| e.name.as_str()).collect();
+    assert!(names.contains(&"cpu-clock"));
+    assert!(names.contains(&"L1-dcache-prefetch-misses"));
+    assert!(!names.contains(&"cycles"));
+    // Every listed name must be accepted by `-e`.
+    for name in &names {
+        assert!(name.parse::<StatEvent>().is_ok(), "{}", name);
+    }
+    let cpu_clock = listing.events.iter().find(|e| e.name == "cpu-clock");
+    assert!(cpu_clock.unwrap().supported);
+}
@@ -4,21 +4,40 @@
 //! <ul>
 //! <li>test</li>
 //! <li>stat</li>
+//! <li>record</li>
+//! <li>report</li>
+//! <li>annotate</li>
+//! <li>bench</li>
+//! <li>top</li>
+//! <li>list</li>
 //! <li>gui</li>
 //! </ul>
 
+mod annotate;
+mod bench;
 mod bindings;
 mod event;
 mod gui;
+mod list;
+mod record;
+mod report;
 mod stat;
+mod symbols;
 mod test;
+mod top;
 mod utils;
 
 extern crate structopt;
+use annotate::*;
+use bench::*;
 use gui::*;
+use list::*;
+use record::*;
+use report::*;
 use stat::*;
 use structopt::StructOpt;
 use test::*;
+use top::*;
 
 /// Define command line options.
 #[derive(Debug, StructOpt)]
@@ -30,6 +49,26 @@ enum Opt {
         about = "Collects hardware/software event counters",
     )]
     Stat(StatOptions),
+    #[structopt(
+        setting = structopt::clap::AppSettings::TrailingVarArg,
+        setting = structopt::clap::AppSettings::AllowLeadingHyphen,
+        name = "record",
+        about = "Samples a command into a data file",
+    )]
+    Record(RecordOptions),
+    #[structopt(name = "report", about = "Summarizes samples from a data file")]
+    Report(ReportOptions),
+    #[structopt(name = "annotate", about = "Shows sampled instructions of a function")]
+    Annotate(AnnotateOptions),
+    #[structopt(
+        name = "bench",
+        about = "Runs scheduler, syscall and memory benchmarks"
+    )]
+    Bench(BenchOptions),
+    #[structopt(name = "top", about = "Shows the hottest functions live")]
+    Top(TopOptions),
+    #[structopt(name = "list", about = "Lists the events this machine supports")]
+    List(ListOptions),
     #[structopt(
         setting = structopt::clap::AppSettings::TrailingVarArg,
         setting = structopt::clap::AppSettings::AllowLeadingHyphen,
@@ -50,6 +89,12 @@ fn main() {
     let opt = Opt::from_args();
     match opt {
         Opt::Stat(x) => run_stat(x),
+        Opt::Record(x) => run_record(x),
+        Opt::Report(x) => run_report(x),
+        Opt::Annotate(x) => run_annotate(x),
+        Opt::Bench(x) => run_bench(x),
+        Opt::Top(x) => run_top(x),
+        Opt::List(x) => run_list(x),
         Opt::Test(x) => run_test(&x),
         Opt::Gui(x) => {
             run_gui(&x).unwrap();
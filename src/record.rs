@@ -0,0 +1,387 @@
+//! # Record driver.
+//! <p> Usage: <em> ruperf record [OPTION] [COMMAND] [ARGS] </em>
+//! Where COMMAND and ARGS are a shell command and it's arguments.
+//! Samples are written to `ruperf.data` unless `-o` says otherwise. </p>
+
+pub mod data;
+
+extern crate structopt;
+use crate::bindings::*;
+use crate::event::cpu::online_cpus;
+use crate::event::open::*;
+use crate::event::ring::{self, RingBuffer};
+use crate::event::sample::*;
+use crate::event::tracepoint::Format;
+use crate::stat::{launch_command_process, StatEvent};
+use crate::symbols::maps::{read_maps, MapEntry};
+use crate::utils::DataError;
+use data::*;
+use os_pipe::pipe;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Sampling frequency used when neither
+/// `--count` nor `--freq` is given.
+const DEFAULT_FREQ: u64 = 4000;
+
+/// How long to block waiting for samples before
+/// checking whether the profiled command exited.
+const POLL_TIMEOUT_MS: i32 = 100;
+
+/// Configuration settings for running record. A program to profile is a
+/// required argument. `cpu-clock` is sampled if no events are specified, which
+/// works without access to a hardware PMU. See `./ruperf record --help` for
+/// more information.
+#[derive(Debug, StructOpt)]
+pub struct RecordOptions {
+    #[structopt(short, long, help = "Event to sample", number_of_values = 1)]
+    pub event: Vec<StatEvent>,
+
+    #[structopt(
+        short = "c",
+        long = "count",
+        help = "Sample every COUNT occurrences of the event",
+        conflicts_with = "freq"
+    )]
+    pub count: Option<u64>,
+
+    #[structopt(
+        short = "F",
+        long = "freq",
+        help = "Samples per second [default: 4000]"
+    )]
+    pub freq: Option<u64>,
+
+    #[structopt(short = "g", long = "call-graph", help = "Record callchains")]
+    pub call_graph: bool,
+
+    #[structopt(
+        short = "m",
+        long = "mmap-pages",
+        help = "Ring buffer data pages per event, a power of two",
+        default_value = "32"
+    )]
+    pub mmap_pages: usize,
+
+    #[structopt(
+        short,
+        long,
+        help = "Data file to write",
+        default_value = "ruperf.data",
+        parse(from_os_str)
+    )]
+    pub output: PathBuf,
+
+    // Allows multiple arguments to be passed, collects everything remaining on
+    // the command line
+    #[structopt(required = true, help = "Command to run")]
+    pub command: Vec<String>,
+}
+
+/// What's needed to decode the records of one event.
+struct EventLayout {
+    /// The sample type the event was opened with.
+    sample_type: u64,
+    /// The read format of `PERF_SAMPLE_READ` values.
+    read_format: u64,
+    /// Layout of the raw data of tracepoint samples.
+    format: Option<Format>,
+}
+
+/// Convert a decoded kernel record into its on-disk form.
+/// `event` is the index of the event whose buffer it came from.
+fn to_data(event: usize, layout: &EventLayout, record: RawRecord) -> Option<DataRecord> {
+    match record {
+        RawRecord::Sample(s) => Some(DataRecord::Sample {
+            event,
+            ip: s.ip,
+            pid: s.pid,
+            tid: s.tid,
+            time: s.time,
+            cpu: s.cpu,
+            period: s.period,
+            kernel: s.misc as u32 & PERF_RECORD_MISC_CPUMODE_MASK == PERF_RECORD_MISC_KERNEL,
+            fields: match &layout.format {
+                Some(format) => format.decode(&s.raw),
+                None => Vec::new(),
+            },
+            callchain: s.callchain,
+            read: s.read,
+        }),
+        RawRecord::Mmap {
+            pid,
+            tid,
+            addr,
+            len,
+            pgoff,
+            filename,
+        } => Some(DataRecord::Mmap {
+            pid,
+            tid,
+            addr,
+            len,
+            pgoff,
+            filename,
+        }),
+        RawRecord::Comm {
+            pid,
+            tid,
+            comm,
+            exec,
+        } => Some(DataRecord::Comm {
+            pid,
+            tid,
+            comm,
+            exec,
+        }),
+        RawRecord::Fork {
+            pid,
+            ppid,
+            tid,
+            time,
+        } => Some(DataRecord::Fork {
+            pid,
+            ppid,
+            tid,
+            time,
+        }),
+        RawRecord::Exit {
+            pid,
+            ppid,
+            tid,
+            time,
+        } => Some(DataRecord::Exit {
+            pid,
+            ppid,
+            tid,
+            time,
+        }),
+        RawRecord::Lost { lost, .. } => Some(DataRecord::Lost { event, lost }),
+        RawRecord::Throttle { .. } | RawRecord::Other(_) => None,
+    }
+}
+
+/// Running totals reported once recording ends.
+#[derive(Default)]
+struct RecordStats {
+    samples: u64,
+    lost: u64,
+    /// Pids seen exec'ing, whose maps are worth snapshotting.
+    execs: Vec<u32>,
+}
+
+/// Move every record currently in `rings` to `writer`.
+/// `owners[i]` is the index of the event `rings[i]` belongs to.
+fn drain_rings(
+    rings: &mut [RingBuffer<'_>],
+    owners: &[usize],
+    layouts: &[EventLayout],
+    writer: &mut DataWriter,
+    stats: &mut RecordStats,
+) -> Result<(), DataError> {
+    for (ring, &owner) in rings.iter_mut().zip(owners) {
+        for raw in ring.drain() {
+            let layout = &layouts[owner];
+            let record = match parse_record(&raw, layout.sample_type, layout.read_format)
+                .and_then(|r| to_data(owner, layout, r))
+            {
+                Some(r) => r,
+                None => continue,
+            };
+            match &record {
+                DataRecord::Sample { .. } => stats.samples += 1,
+                DataRecord::Lost { lost, .. } => stats.lost += lost,
+                DataRecord::Comm {
+                    pid, exec: true, ..
+                } if !stats.execs.contains(pid) => stats.execs.push(*pid),
+                _ => {}
+            }
+            writer.write(&record)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a `/proc/<pid>/maps` snapshot for each of `pids`
+/// whose executable mappings changed since the last one.
+/// Only exec'd processes are snapshotted, before that
+/// their maps are still a copy of ours.
+fn snapshot_maps(
+    pids: &[u32],
+    last: &mut Vec<(u32, Vec<MapEntry>)>,
+    writer: &mut DataWriter,
+) -> Result<(), DataError> {
+    for &pid in pids {
+        // A process that already exited has no mappings left.
+        let maps = match read_maps(pid as i32) {
+            Some(maps) if !maps.is_empty() => maps,
+            _ => continue,
+        };
+        if last.iter().any(|(p, m)| *p == pid && *m == maps) {
+            continue;
+        }
+        last.retain(|(p, _)| *p != pid);
+        last.push((pid, maps.clone()));
+        writer.write(&DataRecord::Maps { pid, maps })?;
+    }
+    Ok(())
+}
+
+/// Report `err`, kill the profiled command `pid` and exit.
+fn fail(pid: libc::pid_t, err: impl std::fmt::Display) -> ! {
+    eprintln!("ruperf record: {}", err);
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+    std::process::exit(1);
+}
+
+/// Run perf record on the given command and event combinations.
+/// Sampling starts when the command is exec'd and samples are
+/// drained to the data file until it exits.
+pub fn run_record(options: RecordOptions) {
+    let mut options = options;
+    if options.event.is_empty() {
+        options.event.push(StatEvent::named("cpu-clock"));
+    }
+    let period = match options.count {
+        Some(count) => SamplePeriod::Period(count),
+        None => SamplePeriod::Frequency(options.freq.unwrap_or(DEFAULT_FREQ)),
+    };
+    let mut sample_type = perf_event_sample_format_PERF_SAMPLE_IP
+        | perf_event_sample_format_PERF_SAMPLE_TID
+        | perf_event_sample_format_PERF_SAMPLE_TIME
+        | perf_event_sample_format_PERF_SAMPLE_CPU
+        | perf_event_sample_format_PERF_SAMPLE_PERIOD;
+    if options.call_graph {
+        sample_type |= perf_event_sample_format_PERF_SAMPLE_CALLCHAIN;
+    }
+
+    // The child's ends are closed here once it's forked,
+    // so a child that dies early is seen as end of file.
+    let pipes = pipe().and_then(|(child_reader, writer)| {
+        let (parent_reader, child_writer) = pipe()?;
+        Ok((child_reader, writer, parent_reader, child_writer))
+    });
+    let (child_reader, mut writer, mut parent_reader, child_writer) = pipes.unwrap_or_else(|e| {
+        eprintln!("ruperf record: can't set up the command: {}", e);
+        std::process::exit(1);
+    });
+    let pid_child = launch_command_process(options.command.clone(), child_reader, child_writer);
+
+    // Inherited events can only be mmap'd per CPU,
+    // so each event is opened once on every CPU.
+    let cpus = online_cpus();
+    let mut events: Vec<Event> = Vec::new();
+    let mut owners: Vec<usize> = Vec::new();
+    let mut layouts: Vec<EventLayout> = Vec::new();
+    for (i, event) in options.event.iter().enumerate() {
+        // Tracepoints are rare enough to sample every hit.
+        let period = match (event.is_tracepoint(), options.count, options.freq) {
+            (true, None, None) => SamplePeriod::Period(1),
+            _ => period,
+        };
+        for cpu in &cpus {
+            let attr =
+                &mut sample_open(event, period, sample_type).unwrap_or_else(|e| fail(pid_child, e));
+            if layouts.len() == i {
+                layouts.push(EventLayout {
+                    sample_type: attr.sample_type,
+                    read_format: attr.read_format,
+                    format: event.tracepoint_format(),
+                });
+            }
+            // Don't sample ourselves setting up the exec,
+            // and follow any threads or children it spawns.
+            attr.set_enable_on_exec(1);
+            attr.set_inherit(1);
+            let event = Event::with_attr(event.clone(), attr, Some(pid_child), *cpu)
+                .unwrap_or_else(|e| fail(pid_child, e));
+            owners.push(i);
+            events.push(event);
+        }
+    }
+    let mut rings: Vec<RingBuffer> = events
+        .iter()
+        .map(|event| {
+            event
+                .fd
+                .mmap(options.mmap_pages)
+                .unwrap_or_else(|e| fail(pid_child, format!("{}, try fewer --mmap-pages", e)))
+        })
+        .collect();
+
+    let header = Header {
+        version: DATA_VERSION,
+        command: options.command.clone(),
+        events: options.event.iter().map(|e| e.to_string()).collect(),
+        sample_types: layouts.iter().map(|l| l.sample_type).collect(),
+        read_formats: layouts.iter().map(|l| l.read_format).collect(),
+        pid: pid_child,
+    };
+    let mut data = DataWriter::create(&options.output, header).unwrap_or_else(|e| {
+        fail(
+            pid_child,
+            format!("can't create {}: {}", options.output.display(), e),
+        )
+    });
+    let mut stats = RecordStats::default();
+
+    // Wait for child to say it is set up to execute.
+    let mut buf = [0];
+    match parent_reader.read(&mut buf) {
+        Ok(1) => {}
+        Ok(_) => fail(pid_child, "the command exited before it started"),
+        Err(e) => fail(pid_child, format!("can't start the command: {}", e)),
+    }
+
+    // Notify child counters are set up.
+    if let Err(e) = writer.write_all(&[1]) {
+        fail(pid_child, format!("can't start the command: {}", e));
+    }
+    drop(writer);
+
+    let mut status: libc::c_int = 0;
+    let mut snapshots: Vec<(u32, Vec<MapEntry>)> = Vec::new();
+    loop {
+        if let Err(e) = ring::poll(&rings, POLL_TIMEOUT_MS) {
+            fail(pid_child, e);
+        }
+        drain_rings(&mut rings, &owners, &layouts, &mut data, &mut stats)
+            .and_then(|_| snapshot_maps(&stats.execs, &mut snapshots, &mut data))
+            .unwrap_or_else(|e| fail(pid_child, e));
+        let result =
+            unsafe { libc::waitpid(pid_child, (&mut status) as *mut libc::c_int, libc::WNOHANG) };
+        if result == pid_child {
+            break;
+        }
+        // Without a child to wait for, nothing more will be recorded.
+        if result == -1 {
+            let err = std::io::Error::last_os_error();
+            if err.raw_os_error() != Some(libc::EINTR) {
+                eprintln!("ruperf record: waiting for the command failed: {}", err);
+                break;
+            }
+        }
+    }
+    // Pick up anything written between the last drain and exit.
+    // The command's pid may already be reaped and reused,
+    // so it isn't killed on failure from here on.
+    let written = drain_rings(&mut rings, &owners, &layouts, &mut data, &mut stats)
+        .and_then(|_| data.finish());
+    if let Err(e) = written {
+        eprintln!("ruperf record: {}", e);
+        std::process::exit(1);
+    }
+
+    eprintln!(
+        "[ ruperf record: Captured and wrote {} samples to '{}' ]",
+        stats.samples,
+        options.output.display()
+    );
+    if stats.lost > 0 {
+        eprintln!(
+            "[ ruperf record: Lost {} samples, try more --mmap-pages ]",
+            stats.lost
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! The on-disk format written by `ruperf record`.
+//!
+//! A data file is a sequence of JSON objects, one
+//! per line. The first line is always a `Header`,
+//! describing how the samples were taken, followed
+//! by the records drained from the ring buffers in
+//! the order they were read.
+
+use crate::event::open::CounterValue;
+use crate::event::tracepoint::TraceField;
+use crate::symbols::maps::MapEntry;
+use crate::utils::DataError;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Bumped whenever the layout of a record changes.
+pub const DATA_VERSION: u32 = 2;
+
+/// Describes a recording session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    /// Command line of the profiled program.
+    pub command: Vec<String>,
+    /// Name of each sampled event, indexed
+    /// by `DataRecord::Sample::event`.
+    pub events: Vec<String>,
+    /// `PERF_SAMPLE_*` mask each event was opened with.
+    pub sample_types: Vec<u64>,
+    /// `PERF_FORMAT_*` mask of each event's `read` values.
+    pub read_formats: Vec<u64>,
+    /// Process id of the profiled program.
+    pub pid: i32,
+}
+
+/// A single line of a data file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DataRecord {
+    Header(Header),
+    Sample {
+        event: usize,
+        ip: u64,
+        pid: u32,
+        tid: u32,
+        time: u64,
+        cpu: u32,
+        period: u64,
+        /// True if the sample was taken in kernel mode.
+        kernel: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        callchain: Vec<u64>,
+        /// Decoded raw data of tracepoint samples.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        fields: Vec<TraceField>,
+        /// Counter values read with the sample, for `:S`
+        /// events. One per group member with `PERF_FORMAT_GROUP`.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        read: Vec<CounterValue>,
+    },
+    Mmap {
+        pid: u32,
+        tid: u32,
+        addr: u64,
+        len: u64,
+        pgoff: u64,
+        filename: String,
+    },
+    Comm {
+        pid: u32,
+        tid: u32,
+        comm: String,
+        exec: bool,
+    },
+    Fork {
+        pid: u32,
+        ppid: u32,
+        tid: u32,
+        time: u64,
+    },
+    Exit {
+        pid: u32,
+        ppid: u32,
+        tid: u32,
+        time: u64,
+    },
+    Lost {
+        event: usize,
+        lost: u64,
+    },
+    /// Executable mappings read from `/proc/<pid>/maps`.
+    Maps {
+        pid: u32,
+        maps: Vec<MapEntry>,
+    },
+}
+
+/// Buffered writer for a data file.
+pub struct DataWriter {
+    out: BufWriter<File>,
+}
+
+impl DataWriter {
+    /// Create (or truncate) the file at
+    /// `path` and write `header` to it.
+    pub fn create(path: &Path, header: Header) -> Result<Self, DataError> {
+        let mut writer = Self {
+            out: BufWriter::new(File::create(path)?),
+        };
+        writer.write(&DataRecord::Header(header))?;
+        Ok(writer)
+    }
+
+    /// Append one record.
+    pub fn write(&mut self, record: &DataRecord) -> Result<(), DataError> {
+        serde_json::to_writer(&mut self.out, record)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Flush buffered records to disk.
+    pub fn finish(mut self) -> Result<(), DataError> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Read a whole data file back, returning
+/// its header and the records that follow.
+pub fn read_data(path: &Path) -> Result<(Header, Vec<DataRecord>), DataError> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = match lines.next() {
+        Some(line) => match serde_json::from_str(&line?)? {
+            DataRecord::Header(h) if h.version == DATA_VERSION => h,
+            _ => return Err(DataError::BadHeader),
+        },
+        None => return Err(DataError::BadHeader),
+    };
+    let mut records = Vec::new();
+    for line in lines {
+        let line = line?;
+        if !line.is_empty() {
+            records.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok((header, records))
+}
@@ -0,0 +1,375 @@
+//! # Report driver.
+//! <p> Usage: <em> ruperf report [OPTION] </em>
+//! Reads the samples written by `ruperf record` and prints
+//! an overhead-sorted table of where they landed. </p>
+
+extern crate structopt;
+use crate::event::sample::{PERF_CONTEXT_KERNEL, PERF_CONTEXT_MAX};
+use crate::record::data::*;
+use crate::symbols::maps::MapEntry;
+use crate::symbols::{Location, Symbolizer};
+use crate::utils::ParseError;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Call graph nodes below this share
+/// of the event's samples are hidden.
+const GRAPH_MIN_PERCENT: f64 = 0.5;
+
+/// Columns samples can be grouped by.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SortKey {
+    Comm,
+    Dso,
+    Sym,
+}
+
+impl FromStr for SortKey {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "comm" => Ok(SortKey::Comm),
+            "dso" => Ok(SortKey::Dso),
+            "sym" => Ok(SortKey::Sym),
+            _ => Err(ParseError::UnknownSortKey),
+        }
+    }
+}
+
+impl SortKey {
+    /// Column heading.
+    fn title(&self) -> &'static str {
+        match self {
+            SortKey::Comm => "Command",
+            SortKey::Dso => "Shared Object",
+            SortKey::Sym => "Symbol",
+        }
+    }
+}
+
+/// Which end of a callchain the call graph is rooted at.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CallGraphOrder {
+    /// Outermost frame first, down to the sampled function.
+    Caller,
+    /// Sampled function first, up through who called it.
+    Callee,
+}
+
+impl FromStr for CallGraphOrder {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "caller" => Ok(CallGraphOrder::Caller),
+            "callee" => Ok(CallGraphOrder::Callee),
+            _ => Err(ParseError::UnknownCallGraph),
+        }
+    }
+}
+
+/// Configuration settings for running report. See `./ruperf report --help`
+/// for more information.
+#[derive(Debug, StructOpt)]
+pub struct ReportOptions {
+    #[structopt(
+        short,
+        long,
+        help = "Data file to read",
+        default_value = "ruperf.data",
+        parse(from_os_str)
+    )]
+    pub input: PathBuf,
+
+    #[structopt(
+        short,
+        long,
+        help = "Comma separated columns to group by: comm, dso, sym",
+        default_value = "comm,dso,sym",
+        use_delimiter = true
+    )]
+    pub sort: Vec<SortKey>,
+
+    #[structopt(
+        short = "G",
+        long = "call-graph",
+        help = "Print callchains rooted at the caller or callee"
+    )]
+    pub call_graph: Option<CallGraphOrder>,
+
+    #[structopt(
+        long = "percent-limit",
+        help = "Hide entries below this overhead percentage",
+        default_value = "0"
+    )]
+    pub percent_limit: f64,
+}
+
+/// A node in a call graph, children keyed by function.
+#[derive(Default)]
+struct CallNode {
+    period: u64,
+    children: BTreeMap<String, CallNode>,
+}
+
+impl CallNode {
+    /// Add `period` along `path`, creating nodes as needed.
+    fn insert(&mut self, path: &[String], period: u64) {
+        self.period += period;
+        if let Some((first, rest)) = path.split_first() {
+            self.children
+                .entry(first.clone())
+                .or_default()
+                .insert(rest, period);
+        }
+    }
+}
+
+/// One row of the report.
+#[derive(Default)]
+struct Entry {
+    period: u64,
+    samples: u64,
+    graph: CallNode,
+}
+
+/// Per-event totals and rows.
+#[derive(Default)]
+struct Histogram {
+    period: u64,
+    samples: u64,
+    lost: u64,
+    entries: HashMap<Vec<String>, Entry>,
+}
+
+/// Symbolize a sample's callchain, innermost frame first.
+/// Return addresses point after the call, so caller
+/// frames are looked up one byte earlier.
+fn resolve_chain(
+    symbolizer: &mut Symbolizer,
+    pid: u32,
+    ip: u64,
+    kernel: bool,
+    callchain: &[u64],
+) -> Vec<Location> {
+    if callchain.is_empty() {
+        return vec![symbolizer.resolve(pid, ip, kernel)];
+    }
+    let mut in_kernel = kernel;
+    let mut frames = Vec::new();
+    for &addr in callchain {
+        if addr >= PERF_CONTEXT_MAX {
+            in_kernel = addr == PERF_CONTEXT_KERNEL;
+            continue;
+        }
+        let lookup = if frames.is_empty() {
+            addr
+        } else {
+            addr.saturating_sub(1)
+        };
+        frames.push(symbolizer.resolve(pid, lookup, in_kernel));
+    }
+    if frames.is_empty() {
+        frames.push(symbolizer.resolve(pid, ip, kernel));
+    }
+    frames
+}
+
+/// Name of the command `tid` of `pid` was running.
+fn comm_of(comms: &HashMap<u32, String>, pid: u32, tid: u32) -> String {
+    comms
+        .get(&tid)
+        .or_else(|| comms.get(&pid))
+        .cloned()
+        .unwrap_or_else(|| format!(":{}", tid))
+}
+
+/// Print `node`'s children as a tree, widest first.
+fn print_graph(node: &CallNode, total: u64, prefix: &str) {
+    let mut children: Vec<(&String, &CallNode)> = node.children.iter().collect();
+    children.sort_by_key(|(_, c)| std::cmp::Reverse(c.period));
+    children.retain(|(_, c)| c.period as f64 * 100.0 / total as f64 >= GRAPH_MIN_PERCENT);
+    for (i, (name, child)) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        println!(
+            "{}{} {:.2}% {}",
+            prefix,
+            if last { "└─" } else { "├─" },
+            child.period as f64 * 100.0 / total as f64,
+            name
+        );
+        let indent = if last { "   " } else { "│  " };
+        print_graph(child, total, &format!("{}{}", prefix, indent));
+    }
+}
+
+/// Print one event's table.
+fn print_histogram(name: &str, hist: &Histogram, options: &ReportOptions) {
+    println!("# Samples: {} of event '{}'", hist.samples, name);
+    println!("# Event count (approx.): {}", hist.period);
+    if hist.lost > 0 {
+        println!("# Lost samples: {}", hist.lost);
+    }
+    println!("#");
+
+    let mut rows: Vec<(&Vec<String>, &Entry)> = hist.entries.iter().collect();
+    rows.sort_by(|a, b| b.1.period.cmp(&a.1.period).then(a.0.cmp(b.0)));
+    let percent = |period: u64| period as f64 * 100.0 / hist.period.max(1) as f64;
+    rows.retain(|(_, e)| percent(e.period) >= options.percent_limit);
+
+    let widths: Vec<usize> = options
+        .sort
+        .iter()
+        .enumerate()
+        .map(|(i, key)| {
+            rows.iter()
+                .map(|(k, _)| k[i].chars().count())
+                .chain(std::iter::once(key.title().len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let columns = |cells: Vec<String>| {
+        cells
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{:<w$}", c, w = w))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!(
+        "# Overhead  {}",
+        columns(options.sort.iter().map(|k| k.title().to_string()).collect())
+    );
+    println!(
+        "# ........  {}",
+        columns(widths.iter().map(|w| ".".repeat(*w)).collect())
+    );
+    println!("#");
+    for (key, entry) in rows {
+        println!("{:>9.2}%  {}", percent(entry.period), columns(key.clone()));
+        if options.call_graph.is_some() && !entry.graph.children.is_empty() {
+            print_graph(&entry.graph, hist.period.max(1), "            ");
+            println!();
+        }
+    }
+    println!();
+}
+
+/// Collect the mappings and command names of every
+/// process in `records`. These must be known before any
+/// sample is resolved, records from different CPUs'
+/// buffers aren't in time order.
+pub fn index_records(records: &[DataRecord]) -> (Symbolizer, HashMap<u32, String>) {
+    let mut symbolizer = Symbolizer::new();
+    let mut comms: HashMap<u32, String> = HashMap::new();
+    for record in records {
+        match record {
+            DataRecord::Mmap {
+                pid,
+                addr,
+                len,
+                pgoff,
+                filename,
+                ..
+            } => symbolizer.add_map(
+                *pid,
+                MapEntry {
+                    start: *addr,
+                    end: addr + len,
+                    pgoff: *pgoff,
+                    path: filename.clone(),
+                },
+            ),
+            DataRecord::Maps { pid, maps } => symbolizer.add_snapshot(*pid, maps.clone()),
+            DataRecord::Fork { pid, ppid, .. } => symbolizer.add_fork(*pid, *ppid),
+            DataRecord::Comm { tid, comm, .. } => {
+                comms.insert(*tid, comm.clone());
+            }
+            _ => {}
+        }
+    }
+    (symbolizer, comms)
+}
+
+/// Run perf report on a data file.
+pub fn run_report(options: ReportOptions) {
+    let (header, records) = match read_data(&options.input) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("ruperf report: {}: {}", options.input.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let (mut symbolizer, comms) = index_records(&records);
+
+    let skip_self = options.sort.contains(&SortKey::Sym);
+    let mut histograms: Vec<Histogram> =
+        header.events.iter().map(|_| Histogram::default()).collect();
+    for record in &records {
+        let (event, ip, pid, tid, period, kernel, callchain) = match record {
+            DataRecord::Sample {
+                event,
+                ip,
+                pid,
+                tid,
+                period,
+                kernel,
+                callchain,
+                ..
+            } => (*event, *ip, *pid, *tid, *period, *kernel, callchain),
+            DataRecord::Lost { event, lost } => {
+                if let Some(h) = histograms.get_mut(*event) {
+                    h.lost += lost;
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        let hist = match histograms.get_mut(event) {
+            Some(h) => h,
+            None => continue,
+        };
+        let frames = resolve_chain(&mut symbolizer, pid, ip, kernel, callchain);
+        let top = &frames[0];
+        let key: Vec<String> = options
+            .sort
+            .iter()
+            .map(|k| match k {
+                SortKey::Comm => comm_of(&comms, pid, tid),
+                SortKey::Dso => top.dso_name().to_string(),
+                SortKey::Sym => format!(
+                    "[{}] {}",
+                    if top.kernel { 'k' } else { '.' },
+                    top.symbol_name()
+                ),
+            })
+            .collect();
+        let entry = hist.entries.entry(key).or_default();
+        entry.period += period;
+        entry.samples += 1;
+        hist.period += period;
+        hist.samples += 1;
+        if let Some(order) = options.call_graph {
+            let mut path: Vec<String> = frames.iter().map(|f| f.symbol_name()).collect();
+            // The row already names the sampled function.
+            if skip_self {
+                path.remove(0);
+            }
+            if order == CallGraphOrder::Caller {
+                path.reverse();
+            }
+            entry.graph.insert(&path, period);
+        }
+    }
+
+    println!("# Command: {}", header.command.join(" "));
+    println!("#");
+    for (name, hist) in header.events.iter().zip(&histograms) {
+        print_histogram(name, hist, &options);
+    }
+}
@@ -2,81 +2,404 @@
 //! <p> Usage: <em> ruperf stat [COMMAND] [ARGS] </em>
 //! Where COMMAND and ARGS are a shell command and it's arguments. </p>
 
+pub mod metrics;
+pub mod output;
+pub mod spec;
+pub mod topdown;
+
 extern crate structopt;
+use crate::bindings::*;
+use crate::event::cpu::{online_cpus, parse_cpu_list, topology};
 use crate::event::open::*;
+use crate::event::task::{is_running, read_comm, threads_of};
+use crate::event::utils::EventErr;
 use crate::utils::ParseError;
+use metrics::{MetricDef, MetricFile};
 use os_pipe::pipe;
-use std::io::prelude::*;
+use output::{Counter, Format, Output, Usage};
+use std::io::{self, prelude::*};
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::{self, FromStr};
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
-/// Supported events
-#[derive(Debug, Copy, Clone)]
-pub enum StatEvent {
-    Cycles,
-    Instructions,
-    TaskClock,
-    ContextSwitches,
-    L1DCacheRead,
-    L1DCacheWrite,
-    L1DCacheReadMiss,
-    L1ICacheReadMiss,
-}
-
-/// Match on each supported event to parse from command line
-impl FromStr for StatEvent {
+pub use spec::StatEvent;
+use spec::SYSFS_PMUS;
+
+/// One `-e` argument: a comma separated list of events.
+/// Events in braces, e.g. `{cycles,instructions}`, are
+/// opened as a group the kernel schedules together.
+#[derive(Debug, Clone)]
+pub struct EventList(pub Vec<Vec<StatEvent>>);
+
+/// Split `s` at the commas separating events, skipping
+/// those inside braces or a PMU's `pmu/terms/`.
+fn split_events(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut in_pmu = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '/' => in_pmu = !in_pmu,
+            ',' if depth == 0 && !in_pmu => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+impl FromStr for EventList {
     type Err = ParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "cycles" => Ok(StatEvent::Cycles),
-            "instructions" => Ok(StatEvent::Instructions),
-            "task-clock" => Ok(StatEvent::TaskClock),
-            "context-switches" => Ok(StatEvent::ContextSwitches),
-            "L1D-cache-reads" => Ok(StatEvent::L1DCacheRead),
-            "L1D-cache-writes" => Ok(StatEvent::L1DCacheWrite),
-            "L1D-cache-read-misses" => Ok(StatEvent::L1DCacheReadMiss),
-            "L1I-cache-read-misses" => Ok(StatEvent::L1ICacheReadMiss),
-            _ => Err(ParseError::InvalidEvent),
+        let braces: &[char] = &['{', '}'];
+        let mut groups = Vec::new();
+        for part in split_events(s) {
+            let part = part.trim();
+            if let Some(inner) = part.strip_prefix('{') {
+                let inner = inner.strip_suffix('}').ok_or(ParseError::InvalidGroup)?;
+                if inner.contains(braces) {
+                    return Err(ParseError::InvalidGroup);
+                }
+                let members = split_events(inner)
+                    .into_iter()
+                    .map(str::parse)
+                    .collect::<Result<Vec<StatEvent>, _>>()?;
+                groups.push(members);
+            } else if part.contains(braces) {
+                return Err(ParseError::InvalidGroup);
+            } else {
+                groups.push(vec![part.parse()?]);
+            }
         }
+        Ok(EventList(groups))
+    }
+}
+
+/// A `-C` argument: CPUs to count on, e.g. `0,2-4`.
+#[derive(Debug, Clone)]
+pub struct CpuList(pub Vec<i32>);
+
+impl FromStr for CpuList {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let online = online_cpus();
+        match parse_cpu_list(s) {
+            Some(cpus) if cpus.iter().all(|c| online.contains(c)) => Ok(CpuList(cpus)),
+            _ => Err(ParseError::InvalidCpuList(s.to_string())),
+        }
+    }
+}
+
+/// A `-p` or `-t` argument: running tasks, e.g. `1234,5678`.
+#[derive(Debug, Clone)]
+pub struct PidList(pub Vec<i32>);
+
+impl FromStr for PidList {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|pid| match pid.trim().parse::<i32>() {
+                Ok(pid) if pid > 0 && is_running(pid) => Ok(pid),
+                _ => Err(ParseError::NoSuchTask(pid.to_string())),
+            })
+            .collect::<Result<_, _>>()
+            .map(PidList)
+    }
+}
+
+/// Shortest `-I` interval, reading counters more
+/// often than this mostly measures ourselves.
+const MIN_INTERVAL_MS: u64 = 10;
+
+/// Parse a `-I` argument in milliseconds.
+fn parse_interval(s: &str) -> Result<u64, ParseError> {
+    match s.trim().parse::<u64>() {
+        Ok(ms) if ms >= MIN_INTERVAL_MS => Ok(ms),
+        _ => Err(ParseError::InvalidInterval),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn interval_test() {
+    assert_eq!(parse_interval("100").unwrap(), 100);
+    assert_eq!(parse_interval(" 10 ").unwrap(), MIN_INTERVAL_MS);
+    assert!(parse_interval("5").is_err());
+    assert!(parse_interval("1s").is_err());
+}
+
+/// Parse a `-r` argument, at least one run.
+fn parse_repeat(s: &str) -> Result<usize, ParseError> {
+    match s.trim().parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(ParseError::InvalidRepeat),
     }
 }
 
-/// Match on each supported event to parse from command line.
-/// Note that the context-switches event runs in kernel mode and requires a perf_event_paranoid setting < 1.
-impl ToString for StatEvent {
-    fn to_string(&self) -> String {
+#[cfg(test)]
+#[test]
+fn repeat_test() {
+    assert_eq!(parse_repeat("3").unwrap(), 3);
+    assert!(parse_repeat("0").is_err());
+    assert!(parse_repeat("-1").is_err());
+}
+
+/// How counts from several CPUs or threads are combined.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Aggregation {
+    /// One total for every CPU or thread.
+    Global,
+    Cpu,
+    Core,
+    Socket,
+    Thread,
+}
+
+impl Aggregation {
+    /// Label of the row the counters of `pid` on `cpu` are counted in.
+    fn label(&self, pid: Option<i32>, cpu: i32) -> Option<String> {
+        let t = topology(cpu);
         match self {
-            StatEvent::Cycles => "cycles".to_string(),
-            StatEvent::Instructions => "instructions".to_string(),
-            StatEvent::TaskClock => "task clock".to_string(),
-            StatEvent::ContextSwitches => "context switches".to_string(),
-            StatEvent::L1DCacheRead => "L1D-cache-reads".to_string(),
-            StatEvent::L1DCacheWrite => "L1D-cache-writes".to_string(),
-            StatEvent::L1DCacheReadMiss => "L1D-cache-read-misses".to_string(),
-            StatEvent::L1ICacheReadMiss => "L1I-cache-read-misses".to_string(),
+            Aggregation::Global => None,
+            Aggregation::Cpu => Some(format!("CPU{}", cpu)),
+            Aggregation::Core => Some(format!("S{}-C{}", t.socket, t.core)),
+            Aggregation::Socket => Some(format!("S{}", t.socket)),
+            Aggregation::Thread => pid.map(|tid| format!("{}-{}", read_comm(tid as u32), tid)),
         }
     }
+
+    /// True if each row sums up several CPUs.
+    fn per_cpus(&self) -> bool {
+        matches!(self, Aggregation::Core | Aggregation::Socket)
+    }
 }
 
-/// Configuration settings for running stat. A program to profile is a required
-/// argument. Default events will run on that program if no events are
-/// specified. Specify events using the flag `-e or --event`. See `./ruperf stat
-/// --help' for more information.
+/// Configuration settings for running stat. Default events will run on the
+/// program if no events are specified. Specify events using the flag `-e or
+/// --event`. See `./ruperf stat --help' for more information. Use `-e
+/// '{cycles,instructions}'` to count events as a group. With `-a` or `-C`
+/// every process on the CPUs is counted, for as long as the command runs or
+/// until Ctrl-C if there is none. `-p` and `-t` attach to running tasks
+/// and count until they exit or Ctrl-C. Threads and processes started by
+/// the counted tasks are included unless `--no-inherit` is given.
+/// `--per-thread` shows each thread on its own, including those a
+/// command starts, which are looked for while it runs. `-I` prints how
+/// much each counter went up every so many milliseconds. `-r` runs the
+/// command several times and shows how much the counts vary.
+/// Metrics such as instructions per cycle are shown when the events
+/// they need are counted, `-M` adds those events, see [`metrics`].
+/// `--topdown` breaks the CPU's time down into retiring, bad speculation,
+/// frontend and backend bound, see [`topdown`]. `-x` and `--json` write
+/// results for other programs to read, see [`output`], to stdout or the
+/// file given with `-o`.
 
 #[derive(Debug, StructOpt)]
 pub struct StatOptions {
-    #[structopt(short, long, help = "Event to collect", number_of_values = 1)]
-    pub event: Vec<StatEvent>,
+    #[structopt(
+        short,
+        long,
+        help = "Events to collect, {a,b} counts a and b as a group",
+        number_of_values = 1
+    )]
+    pub event: Vec<EventList>,
+
+    #[structopt(short, long, help = "Count every process on every CPU")]
+    pub all_cpus: bool,
+
+    #[structopt(
+        short = "C",
+        long = "cpu",
+        help = "Count every process on these CPUs, e.g. 0,2-4"
+    )]
+    pub cpu: Option<CpuList>,
+
+    #[structopt(
+        long,
+        help = "Show counts for each CPU, implies -a",
+        conflicts_with_all = &["per-core", "per-socket"]
+    )]
+    pub per_cpu: bool,
+
+    #[structopt(
+        long,
+        help = "Show counts for each core, implies -a",
+        conflicts_with = "per-socket"
+    )]
+    pub per_core: bool,
+
+    #[structopt(long, help = "Show counts for each socket, implies -a")]
+    pub per_socket: bool,
+
+    #[structopt(
+        short,
+        long,
+        help = "Count every thread of these running processes, e.g. 1234,5678",
+        conflicts_with_all = &["tid", "command", "all-cpus", "cpu", "per-cpu", "per-core", "per-socket"]
+    )]
+    pub pid: Option<PidList>,
+
+    #[structopt(
+        short,
+        long,
+        help = "Count these running threads",
+        conflicts_with_all = &["command", "all-cpus", "cpu", "per-cpu", "per-core", "per-socket"]
+    )]
+    pub tid: Option<PidList>,
+
+    #[structopt(long, help = "Don't count threads and processes the command starts")]
+    pub no_inherit: bool,
+
+    #[structopt(
+        long,
+        help = "Show counts for each thread of -p, -t or the command",
+        conflicts_with_all = &["all-cpus", "cpu", "per-cpu", "per-core", "per-socket", "repeat"]
+    )]
+    pub per_thread: bool,
+
+    #[structopt(
+        short = "I",
+        long,
+        help = "Print counts every MS milliseconds while counting",
+        value_name = "MS",
+        parse(try_from_str = parse_interval)
+    )]
+    pub interval_print: Option<u64>,
+
+    #[structopt(
+        short,
+        long,
+        help = "Run the command N times and show the mean and spread of each count",
+        value_name = "N",
+        requires = "command",
+        conflicts_with = "interval-print",
+        parse(try_from_str = parse_repeat)
+    )]
+    pub repeat: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Run the command N more times first, ignoring their counts",
+        value_name = "N",
+        requires = "command"
+    )]
+    pub warmup: Option<usize>,
+
+    #[structopt(
+        short = "M",
+        long,
+        help = "Metrics to show, opening the events they need, e.g. ipc,ghz",
+        number_of_values = 1,
+        require_delimiter = true
+    )]
+    pub metrics: Vec<String>,
+
+    #[structopt(long, help = "File of extra metrics, one 'name = expression' per line")]
+    pub metric_file: Option<MetricFile>,
+
+    #[structopt(long, help = "Show where the CPU's issue slots went")]
+    pub topdown: bool,
+
+    #[structopt(
+        long,
+        help = "Top-down level, 2 splits each share of level 1 further",
+        possible_values = &["1", "2"],
+        requires = "topdown"
+    )]
+    pub td_level: Option<u8>,
+
+    #[structopt(
+        short = "x",
+        long,
+        help = "Print CSV, with this separator between fields",
+        value_name = "SEP",
+        conflicts_with = "json"
+    )]
+    pub field_separator: Option<String>,
+
+    #[structopt(long, help = "Print a JSON object per counter")]
+    pub json: bool,
+
+    #[structopt(
+        short,
+        long,
+        help = "Write results to this file instead of stdout",
+        parse(from_os_str)
+    )]
+    pub output: Option<PathBuf>,
 
     // Allows multiple arguments to be passed, collects everything remaining on
     // the command line
-    #[structopt(required = true, help = "Command to run")]
+    #[structopt(
+        required_unless_one = &["all-cpus", "cpu", "per-cpu", "per-core", "per-socket", "pid", "tid"],
+        help = "Command to run"
+    )]
     pub command: Vec<String>,
 }
 
+impl StatOptions {
+    fn aggregation(&self) -> Aggregation {
+        if self.per_cpu {
+            Aggregation::Cpu
+        } else if self.per_core {
+            Aggregation::Core
+        } else if self.per_socket {
+            Aggregation::Socket
+        } else if self.per_thread {
+            Aggregation::Thread
+        } else {
+            Aggregation::Global
+        }
+    }
+
+    /// CPUs to count every process on, or
+    /// `None` to count just the command.
+    fn cpus(&self) -> Option<Vec<i32>> {
+        match &self.cpu {
+            Some(list) => Some(list.0.clone()),
+            None if self.all_cpus || self.per_cpu || self.per_core || self.per_socket => {
+                Some(online_cpus())
+            }
+            None => None,
+        }
+    }
+
+    /// Running threads to attach to, if any.
+    fn threads(&self) -> Option<Vec<i32>> {
+        match (&self.pid, &self.tid) {
+            (Some(pids), _) => Some(pids.0.iter().flat_map(|&pid| threads_of(pid)).collect()),
+            (None, Some(tids)) => Some(tids.0.clone()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Set once Ctrl-C is pressed.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Let Ctrl-C stop counting rather than kill us, so
+/// the counts so far still get printed. A command run
+/// in the foreground gets the signal too and exits.
+fn catch_interrupt() {
+    let handler: extern "C" fn(libc::c_int) = on_interrupt;
+    unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+}
+
 pub fn launch_command_process(
     command: Vec<String>,
     mut child_reader: os_pipe::PipeReader,
@@ -105,87 +428,894 @@ pub fn launch_command_process(
 }
 
 /// Run perf stat on the given command and event combinations.
-/// Currently starts and stops a cycles timer in serial for each event specified.
+/// Each group's members are started and stopped together
+/// through their leader, events outside braces are
+/// groups of their own. System wide, every group is
+/// opened once per CPU. With `-r` the command is run
+/// again and again, with fresh counters each time.
 pub fn run_stat(options: StatOptions) {
-    // In future rather than starting and stopping counter
-    // in series for each event, events will have the ability
-    // to be added in groups that will coordinate their timing.
-    let mut options = options;
+    let cpus = options.cpus();
+    let threads = options.threads();
+    let mut groups: Vec<Vec<StatEvent>> = options
+        .event
+        .iter()
+        .flat_map(|list| list.0.clone())
+        .collect();
+
+    // The usual events, unless -e or -M chose some.
+    if options.event.is_empty() && options.metrics.is_empty() {
+        groups = [
+            "cycles",
+            "instructions",
+            "task-clock",
+            "context-switches",
+            "L1D-cache-reads",
+            "L1D-cache-writes",
+            "L1D-cache-read-misses",
+            "L1I-cache-read-misses",
+        ]
+        .iter()
+        .map(|e| vec![StatEvent::named(e)])
+        .collect();
+    }
+
+    // Without the events it needs, --topdown says so
+    // and only the other events are counted.
+    let level = options.td_level.unwrap_or(1);
+    let topdown = options
+        .topdown
+        .then(|| topdown::detect(Path::new(SYSFS_PMUS)))
+        .flatten();
+    let mut topdown_defs = Vec::new();
+    if let Some(td) = &topdown {
+        if level >= 2 && !td.has_level2() {
+            eprintln!("ruperf stat: this CPU has no level 2 top-down events, showing level 1");
+        }
+        let found = td
+            .events(level)
+            .and_then(|events| td.metrics(level).map(|defs| (events, defs)));
+        match found {
+            Ok((events, defs)) => {
+                groups.push(events);
+                topdown_defs = defs;
+            }
+            Err(e) => eprintln!("ruperf stat: top-down not available: {}", e),
+        }
+    } else if options.topdown {
+        eprintln!(
+            "ruperf stat: --topdown needs a CPU PMU with top-down events, \
+             such as cpu/slots/ or cpu/topdown-total-slots/, this one has none"
+        );
+    }
+
+    // Open what the metrics asked for need as a group of their
+    // own, so their counts are all taken over the same time.
+    let mut defs = metrics::builtin();
+    let file = match (&options.metric_file, MetricFile::default_path()) {
+        (Some(file), _) => Some(file.clone()),
+        (None, Some(path)) if path.exists() => {
+            match MetricFile::from_str(&path.to_string_lossy()) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    eprintln!("ruperf stat: {}", e);
+                    None
+                }
+            }
+        }
+        (None, _) => None,
+    };
+    defs.extend(file.into_iter().flat_map(|f| f.0));
+    defs.extend(topdown_defs);
+    for name in &options.metrics {
+        let def = match defs.iter().find(|d| &d.name == name) {
+            Some(def) => def,
+            None => {
+                eprintln!("ruperf stat: unknown metric '{}', available metrics:", name);
+                for def in &defs {
+                    eprintln!("  {:<20} {}", def.name, def.label);
+                }
+                std::process::exit(1);
+            }
+        };
+        let events: Vec<&StatEvent> = groups.iter().flatten().collect();
+        let missing = metrics::missing_events(def, &events);
+        if !missing.is_empty() {
+            groups.push(missing);
+        }
+    }
+
+    let ids = |list: &PidList| {
+        let ids: Vec<String> = list.0.iter().map(|id| id.to_string()).collect();
+        ids.join(",")
+    };
+    let target = match (&options.cpu, &cpus, &options.pid, &options.tid) {
+        (Some(_), _, _, _) => format!("CPU(s) {}", cpu_list(cpus.as_ref().unwrap())),
+        (None, Some(_), _, _) => "system wide".to_string(),
+        (None, None, Some(pids), _) => format!("process id {}", ids(pids)),
+        (None, None, None, Some(tids)) => format!("thread id {}", ids(tids)),
+        (None, None, None, None) => options.command.first().unwrap().clone(),
+    };
+    let header = match options.repeat.unwrap_or(1) {
+        1 => format!("Performance counter stats for '{}:'\n", target),
+        n => format!("Performance counter stats for '{}' ({} runs):\n", target, n),
+    };
+
+    let format = match (&options.field_separator, options.json) {
+        (Some(sep), _) => Format::Csv(sep.clone()),
+        (None, true) => Format::Json,
+        (None, false) => Format::Human,
+    };
+    let mut output = Output::new(format, options.output.as_deref()).unwrap_or_else(|e| {
+        let path = options.output.as_deref().unwrap_or_else(|| Path::new("-"));
+        eprintln!("ruperf stat: can't create {}: {}", path.display(), e);
+        std::process::exit(1);
+    });
+
+    catch_interrupt();
+    let runs = count_runs(
+        &options,
+        &groups,
+        &defs,
+        &cpus,
+        &threads,
+        &header,
+        &mut output,
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("ruperf stat: can't write results: {}", e);
+        std::process::exit(1);
+    });
+
+    // Exit the way the command last did, so
+    // scripts can tell whether it failed.
+    if let Some(exit) = runs.last().and_then(|r| r.exit) {
+        if let Some(signal) = exit.signal() {
+            let name = unsafe { std::ffi::CStr::from_ptr(libc::strsignal(signal)) };
+            eprintln!(
+                "ruperf stat: '{}' was killed by signal {} ({})",
+                options.command[0],
+                signal,
+                name.to_string_lossy()
+            );
+        }
+        std::process::exit(exit.code());
+    }
+}
+
+/// Count every run, writing the results to `output` under
+/// `header` as they are wanted, and return the runs.
+fn count_runs(
+    options: &StatOptions,
+    groups: &[Vec<StatEvent>],
+    defs: &[MetricDef],
+    cpus: &Option<Vec<i32>>,
+    threads: &Option<Vec<i32>>,
+    header: &str,
+    output: &mut Output,
+) -> io::Result<Vec<Run>> {
+    if options.interval_print.is_some() {
+        output.header(header)?;
+    }
+    let repeat = options.repeat.unwrap_or(1);
+    let warmup = options.warmup.unwrap_or(0);
+    // Ctrl-C ends the current run and skips the rest.
+    // Every run is written out in machine readable
+    // formats, people only get the summary.
+    let aggregation = options.aggregation();
+    let human = output.is_human();
+    let mut runs = Vec::new();
+    for i in 0..warmup + repeat {
+        let run = count(options, groups, defs, cpus, threads, output, i == 0)?;
+        if i >= warmup {
+            if repeat > 1 && !human {
+                let run_number = Some(runs.len() + 1);
+                let counters = to_counters(&run.rows, groups, defs, aggregation, run.elapsed, None);
+                let counters: Vec<Counter> = counters
+                    .into_iter()
+                    .map(|c| Counter {
+                        run: run_number,
+                        ..c
+                    })
+                    .collect();
+                output.header(header)?;
+                output.counters(&counters)?;
+            }
+            runs.push(run);
+        }
+        if INTERRUPTED.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+
+    match runs.as_slice() {
+        [] => {}
+        [run] => {
+            if options.interval_print.is_none() {
+                output.header(header)?;
+                let counters = to_counters(&run.rows, groups, defs, aggregation, run.elapsed, None);
+                output.counters(&counters)?;
+            }
+            let usage = run.exit.map(|e| e.usage);
+            output.elapsed(run.elapsed.as_secs_f64(), None, usage)?;
+        }
+        runs => {
+            let elapsed: Vec<f64> = runs.iter().map(|r| r.elapsed.as_secs_f64()).collect();
+            let elapsed = Spread::of(&elapsed);
+            output.header(header)?;
+            output.counters(&summarize(runs, groups, defs, aggregation))?;
+            let usages: Vec<Usage> = runs
+                .iter()
+                .filter_map(|r| r.exit.map(|e| e.usage))
+                .collect();
+            let spread = Some((elapsed.stddev, elapsed.relative()));
+            output.elapsed(elapsed.mean, spread, Some(mean_usage(&usages)))?;
+        }
+    }
+    Ok(runs)
+}
 
-    struct EventCounter {
-        event: Event,
-        start: isize,
-        stop: isize,
+/// Counts of one run of the command, or of the whole
+/// time spent counting when there is no command.
+struct Run {
+    rows: Vec<Row>,
+    elapsed: Duration,
+    /// How the command ended, if there is one.
+    exit: Option<Exit>,
+}
+
+/// How the command ended and what it used.
+#[derive(Debug, Clone, Copy)]
+struct Exit {
+    /// Status as `wait4` returns it.
+    status: libc::c_int,
+    usage: Usage,
+}
+
+impl Exit {
+    /// What we exit with: the command's own exit
+    /// code, or 128 plus the signal that killed it.
+    fn code(&self) -> i32 {
+        if libc::WIFSIGNALED(self.status) {
+            128 + libc::WTERMSIG(self.status)
+        } else {
+            libc::WEXITSTATUS(self.status)
+        }
     }
 
-    let mut event_list: Vec<EventCounter> = Vec::new();
+    /// The signal that killed the command, if one did.
+    fn signal(&self) -> Option<i32> {
+        Some(libc::WTERMSIG(self.status)).filter(|_| libc::WIFSIGNALED(self.status))
+    }
+}
+
+/// Reap `pid` if it has exited, waiting for it to if `block`.
+/// `None` if it hasn't, or if waiting failed, e.g. because
+/// `SIGCHLD` is ignored and the kernel reaped it already.
+fn reap(pid: i32, block: bool) -> Option<Exit> {
+    let mut status: libc::c_int = 0;
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    let flags = if block { 0 } else { libc::WNOHANG };
+    let result = loop {
+        let result = unsafe { libc::wait4(pid, &mut status, flags, &mut rusage) };
+        if result != -1 {
+            break result;
+        }
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EINTR) {
+            eprintln!("ruperf stat: waiting for the command failed: {}", err);
+            return None;
+        }
+    };
+    if result != pid {
+        return None;
+    }
+    let seconds = |t: libc::timeval| t.tv_sec as f64 + t.tv_usec as f64 / 1_000_000.0;
+    let usage = Usage {
+        user: seconds(rusage.ru_utime),
+        sys: seconds(rusage.ru_stime),
+        max_rss_kb: rusage.ru_maxrss as u64,
+        minor_faults: rusage.ru_minflt as u64,
+        major_faults: rusage.ru_majflt as u64,
+    };
+    Some(Exit { status, usage })
+}
+
+/// The mean of what the command used over several runs.
+fn mean_usage(usages: &[Usage]) -> Usage {
+    let n = usages.len().max(1);
+    let sum = |f: fn(&Usage) -> u64| usages.iter().map(f).sum::<u64>() / n as u64;
+    Usage {
+        user: usages.iter().map(|u| u.user).sum::<f64>() / n as f64,
+        sys: usages.iter().map(|u| u.sys).sum::<f64>() / n as f64,
+        max_rss_kb: sum(|u| u.max_rss_kb),
+        minor_faults: sum(|u| u.minor_faults),
+        major_faults: sum(|u| u.major_faults),
+    }
+}
 
-    let (reader, mut writer) = pipe().unwrap();
-    let (mut parent_reader, parent_writer) = pipe().unwrap();
+#[cfg(test)]
+#[test]
+fn exit_test() {
+    let exit = |status| Exit {
+        status,
+        usage: Usage::default(),
+    };
+    assert_eq!((exit(3 << 8).code(), exit(3 << 8).signal()), (3, None));
+    assert_eq!((exit(9).code(), exit(9).signal()), (137, Some(9)));
+    let usage = Usage {
+        user: 1.0,
+        max_rss_kb: 100,
+        ..Usage::default()
+    };
+    let mean = mean_usage(&[usage, Usage::default()]);
+    assert_eq!((mean.user, mean.max_rss_kb), (0.5, 50));
+}
 
-    let child_reader = reader.try_clone().unwrap();
-    let child_writer = parent_writer.try_clone().unwrap();
-    let pid_child = launch_command_process(options.command.clone(), child_reader, child_writer);
+/// A group opened on one target, or why it couldn't be.
+type Opened = Result<EventGroup, EventErr>;
 
-    if options.event.is_empty() {
-        options.event.push(StatEvent::Cycles);
-        options.event.push(StatEvent::Instructions);
-        options.event.push(StatEvent::TaskClock);
-        options.event.push(StatEvent::ContextSwitches);
-        options.event.push(StatEvent::L1DCacheRead);
-        options.event.push(StatEvent::L1DCacheWrite);
-        options.event.push(StatEvent::L1DCacheReadMiss);
-        options.event.push(StatEvent::L1ICacheReadMiss);
+/// Open and start every group on every target, then count
+/// until the command exits, the attached threads are gone
+/// or Ctrl-C. With `-I` the counts are also written to
+/// `output` every interval along the way. With `warn`,
+/// groups that can't be opened are reported on stderr.
+fn count(
+    options: &StatOptions,
+    groups: &[Vec<StatEvent>],
+    defs: &[MetricDef],
+    cpus: &Option<Vec<i32>>,
+    threads: &Option<Vec<i32>>,
+    output: &mut Output,
+    warn: bool,
+) -> io::Result<Run> {
+    let mut child = None;
+    if !options.command.is_empty() {
+        let (reader, writer) = pipe().unwrap();
+        let (parent_reader, parent_writer) = pipe().unwrap();
+        let child_reader = reader.try_clone().unwrap();
+        let child_writer = parent_writer.try_clone().unwrap();
+        let pid = launch_command_process(options.command.clone(), child_reader, child_writer);
+        child = Some((pid, parent_reader, writer));
     }
 
-    for event in &options.event {
-        event_list.push(EventCounter {
-            event: Event::new(*event, Some(pid_child)),
-            start: 0,
-            stop: 0,
-        });
+    // Each target is a CPU to count everything on, or
+    // -1 when counting the command or attached threads.
+    let targets: Vec<(Option<i32>, i32)> = match (cpus, threads) {
+        (Some(cpus), _) => cpus.iter().map(|&cpu| (Some(-1), cpu)).collect(),
+        (None, Some(tids)) => tids.iter().map(|&tid| (Some(tid), -1)).collect(),
+        (None, None) => vec![(child.as_ref().map(|(pid, _, _)| *pid), -1)],
+    };
+    // Each thread's own counts are wanted with --per-thread,
+    // and system wide counters already see every task.
+    let inherit = !options.no_inherit && !options.per_thread && cpus.is_none();
+    let aggregation = options.aggregation();
+    let mut opened: Vec<(Option<String>, Vec<Opened>)> = targets
+        .iter()
+        .map(|&(pid, cpu)| {
+            let on_cpu = groups
+                .iter()
+                .map(|g| EventGroup::on_cpu(g, pid, cpu, inherit));
+            (aggregation.label(pid, cpu), on_cpu.collect())
+        })
+        .collect();
+    // Groups that can't be opened are shown as not supported,
+    // saying why once however many targets they failed on.
+    let mut warnings = Warnings {
+        print: warn,
+        seen: Vec::new(),
+    };
+    for error in opened
+        .iter()
+        .flat_map(|(_, g)| g)
+        .filter_map(|g| g.as_ref().err())
+    {
+        warnings.warn(error.to_string());
     }
 
     // Wait for child to say it is set up to execute.
-    let mut buf = [0];
-    let nread = parent_reader.read(&mut buf).unwrap();
-    assert_eq!(nread, 1);
+    if let Some((_, parent_reader, _)) = child.as_mut() {
+        let mut buf = [0];
+        let nread = parent_reader.read(&mut buf).unwrap();
+        assert_eq!(nread, 1);
+    }
 
-    for e in event_list.iter_mut() {
-        e.start = e.event.start_counter().unwrap();
+    // So are groups that can't be started.
+    for group in opened.iter_mut().flat_map(|(_, groups)| groups) {
+        start_group(group, &mut warnings);
     }
     let now = Instant::now();
     // Notify child counters are set up.
-    writer.write_all(&[1]).unwrap();
-    drop(writer);
+    let child = child.map(|(pid, _, mut writer)| {
+        writer.write_all(&[1]).unwrap();
+        pid
+    });
+    let mut following = child
+        .filter(|_| options.per_thread)
+        .map(|pid| Following::new(pid, &options.command[0], &mut opened));
 
-    // Wait for process to exit.
-    let mut status: libc::c_int = 0;
-    let result = unsafe { libc::waitpid(pid_child, (&mut status) as *mut libc::c_int, 0) };
-    assert_eq!(result, pid_child);
-    let t = now.elapsed();
-    for e in event_list.iter_mut() {
-        e.stop = e.event.stop_counter().unwrap();
+    let mut exit = None;
+    let mut finished = || match child {
+        Some(pid) => {
+            // Checked first, so a command that can't be
+            // waited for still ends counting once it's gone.
+            let gone = !is_running(pid);
+            exit = reap(pid, false);
+            exit.is_some() || gone
+        }
+        None => {
+            let attached = |tids: &Vec<i32>| tids.iter().any(|&tid| is_running(tid));
+            INTERRUPTED.load(Ordering::SeqCst) || !threads.as_ref().is_none_or(attached)
+        }
+    };
+
+    let (rows, elapsed) = match (options.interval_print, child) {
+        (None, Some(pid_child)) if following.is_none() => {
+            // Wait for process to exit.
+            exit = reap(pid_child, true);
+            let elapsed = now.elapsed();
+            (read_rows(&opened, groups, true, &mut warnings), elapsed)
+        }
+        (None, _) => {
+            // Threads the command starts are looked
+            // for often, so few are missed.
+            let step = Duration::from_millis(if following.is_some() { 10 } else { 100 });
+            while !finished() {
+                if let Some(following) = following.as_mut() {
+                    following.rescan(&mut opened, groups, &mut warnings);
+                }
+                thread::sleep(step);
+            }
+            let elapsed = now.elapsed();
+            (read_rows(&opened, groups, true, &mut warnings), elapsed)
+        }
+        (Some(ms), _) => {
+            // Poll a child often so its exit isn't
+            // noticed long after it happened.
+            let step = Duration::from_millis(if child.is_some() { 10 } else { 100 });
+            let interval = Duration::from_millis(ms);
+            let mut last = (
+                Duration::from_secs(0),
+                read_rows(&opened, groups, false, &mut warnings),
+            );
+            let mut next = interval;
+            loop {
+                let due = next.saturating_sub(now.elapsed());
+                thread::sleep(due.min(step));
+                let done = finished();
+                if let (Some(following), false) = (following.as_mut(), done) {
+                    following.rescan(&mut opened, groups, &mut warnings);
+                }
+                if !done && now.elapsed() < next {
+                    continue;
+                }
+                // The last interval ends early, when counting stops.
+                let time = now.elapsed();
+                let rows = read_rows(&opened, groups, done, &mut warnings);
+                let changes = delta(&rows, &last.1);
+                let span = time - last.0;
+                let counters = to_counters(&changes, groups, defs, aggregation, span, Some(time));
+                output.counters(&counters)?;
+                if done {
+                    break (rows, time);
+                }
+                last = (time, rows);
+                while next <= time {
+                    next += interval;
+                }
+            }
+        }
+    };
+    Ok(Run {
+        rows,
+        elapsed,
+        exit,
+    })
+}
+
+/// Mean of a count over several runs and how much it varied.
+#[derive(Debug)]
+struct Spread {
+    mean: f64,
+    /// Sample standard deviation.
+    stddev: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Spread {
+    fn of(values: &[f64]) -> Self {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        let squares: f64 = values.iter().map(|v| (v - mean) * (v - mean)).sum();
+        Spread {
+            mean,
+            stddev: if n > 1.0 {
+                (squares / (n - 1.0)).sqrt()
+            } else {
+                0.0
+            },
+            min: values.iter().copied().fold(f64::INFINITY, f64::min),
+            max: values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        }
     }
 
-    println!(
-        "Performance counter stats for '{}:'\n",
-        options.command.get(0).unwrap()
-    );
+    /// The standard deviation as a percentage of the mean.
+    fn relative(&self) -> f64 {
+        if self.mean == 0.0 {
+            return 0.0;
+        }
+        self.stddev * 100.0 / self.mean
+    }
+}
 
-    for event in event_list {
-        if matches!(event.event.event, StatEvent::TaskClock) {
-            println!(
-                " {:.2} msec task-clock\n CPU utilized: {:.3}",
-                (event.stop - event.start) as f64 / 1_000_000.0,
-                (event.stop - event.start) as f64 / t.as_nanos() as f64
-            );
-        } else {
-            println!(
-                " Number of {}: {}",
-                event.event.event.to_string(),
-                event.stop - event.start
-            );
+#[cfg(test)]
+#[test]
+fn spread_test() {
+    let spread = Spread::of(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]);
+    assert_eq!(spread.mean, 5.0);
+    assert!((spread.stddev - 2.138).abs() < 0.001);
+    assert_eq!((spread.min, spread.max), (2.0, 9.0));
+    assert!((spread.relative() - 42.76).abs() < 0.01);
+    assert_eq!(Spread::of(&[3.0]).relative(), 0.0);
+}
+
+/// The mean of each count over `runs` with its relative
+/// standard deviation, smallest and largest value. Runs
+/// where an event never got on the PMU are left out of
+/// its numbers.
+fn summarize(
+    runs: &[Run],
+    groups: &[Vec<StatEvent>],
+    defs: &[MetricDef],
+    aggregation: Aggregation,
+) -> Vec<Counter> {
+    let elapsed: Vec<f64> = runs.iter().map(|r| r.elapsed.as_secs_f64()).collect();
+    let elapsed = Spread::of(&elapsed);
+    let mut counters = Vec::new();
+    for (r, row) in runs[0].rows.iter().enumerate() {
+        let start = counters.len();
+        for (g, group) in groups.iter().enumerate() {
+            for (e, event) in group.iter().enumerate() {
+                let mut total = CounterValue::default();
+                let mut values = Vec::new();
+                for run in runs {
+                    let count = run.rows[r].counts[g][e];
+                    total += count;
+                    values.extend(count.scaled().map(|v| v as f64));
+                }
+                let n = runs.len() as u64;
+                let mut counter = Counter {
+                    interval: None,
+                    run: None,
+                    aggregate: row.label.clone(),
+                    members: Some(row.members).filter(|_| aggregation.per_cpus()),
+                    event: event.to_string(),
+                    supported: row.supported[g],
+                    value: total.value / n,
+                    scaled: None,
+                    unit: unit(event),
+                    enabled: total.enabled / n,
+                    running: total.running / n,
+                    running_percent: total.running_percent(),
+                    seconds: elapsed.mean,
+                    stddev_percent: None,
+                    min: None,
+                    max: None,
+                    metrics: Vec::new(),
+                };
+                if !values.is_empty() {
+                    let spread = Spread::of(&values);
+                    counter.scaled = Some(spread.mean.round() as u64);
+                    counter.stddev_percent = Some(spread.relative());
+                    counter.min = Some(spread.min as u64);
+                    counter.max = Some(spread.max as u64);
+                }
+                counters.push(counter);
+            }
+        }
+        let events: Vec<&StatEvent> = groups.iter().flatten().collect();
+        attach_metrics(&mut counters[start..], &events, defs, elapsed.mean);
+    }
+    counters
+}
+
+/// Counts of one output row: every CPU or thread sharing a label.
+#[derive(Clone)]
+struct Row {
+    label: Option<String>,
+    /// How many CPUs or threads were summed up.
+    members: usize,
+    /// Counts of each group's events.
+    counts: Vec<Vec<CounterValue>>,
+    /// Whether each group opened on any of them.
+    supported: Vec<bool>,
+}
+
+/// Reasons counting failed, each said once on stderr.
+struct Warnings {
+    /// False to keep quiet, e.g. on repeated runs.
+    print: bool,
+    seen: Vec<String>,
+}
+
+impl Warnings {
+    fn warn(&mut self, reason: String) {
+        if !self.seen.contains(&reason) {
+            if self.print {
+                eprintln!("ruperf stat: {}", reason);
+            }
+            self.seen.push(reason);
+        }
+    }
+}
+
+/// Start counting `group`, replacing it with
+/// the error if it can't be started.
+fn start_group(group: &mut Opened, warnings: &mut Warnings) {
+    let started = match group {
+        Ok(g) => g.start().map_err(|e| g.count_error(e)),
+        Err(_) => return,
+    };
+    if let Err(err) = started {
+        warnings.warn(err.to_string());
+        *group = Err(err);
+    }
+}
+
+/// Threads of a launched command counted each on their own with
+/// `--per-thread`. Those it starts are found by looking through
+/// `/proc/<pid>/task` again every so often while counting.
+struct Following {
+    pid: i32,
+    /// Thread counted by each target in `opened`.
+    tids: Vec<i32>,
+}
+
+impl Following {
+    /// Follow the command `pid`, its only target so far being the
+    /// first in `opened`. That one is labelled by the command's name
+    /// since the child may not have exec'd yet, and the kernel names
+    /// a task after the first 15 bytes of the file it runs.
+    fn new(pid: i32, command: &str, opened: &mut [(Option<String>, Vec<Opened>)]) -> Self {
+        let name = Path::new(command)
+            .file_name()
+            .map_or(command.as_bytes(), |name| name.as_bytes());
+        let comm = String::from_utf8_lossy(&name[..name.len().min(15)]);
+        opened[0].0 = Some(format!("{}-{}", comm, pid));
+        Following {
+            pid,
+            tids: vec![pid],
+        }
+    }
+
+    /// Open and start the groups on threads started since the last
+    /// look. Rows of running threads are labelled again, in case
+    /// they were renamed or exec'd.
+    fn rescan(
+        &mut self,
+        opened: &mut Vec<(Option<String>, Vec<Opened>)>,
+        groups: &[Vec<StatEvent>],
+        warnings: &mut Warnings,
+    ) {
+        for (&tid, (label, _)) in self.tids.iter().zip(opened.iter_mut()) {
+            if is_running(tid) {
+                *label = Aggregation::Thread.label(Some(tid), -1);
+            }
+        }
+        for tid in threads_of(self.pid) {
+            if self.tids.contains(&tid) {
+                continue;
+            }
+            let mut on_tid: Vec<Opened> = groups
+                .iter()
+                .map(|g| EventGroup::on_cpu(g, Some(tid), -1, false))
+                .collect();
+            // Threads that exited before they could
+            // be opened had nothing worth showing.
+            if on_tid.iter().all(Result::is_err) && !is_running(tid) {
+                continue;
+            }
+            for group in &mut on_tid {
+                if let Err(err) = group {
+                    warnings.warn(err.to_string());
+                }
+                start_group(group, warnings);
+            }
+            self.tids.push(tid);
+            opened.push((Aggregation::Thread.label(Some(tid), -1), on_tid));
+        }
+    }
+}
+
+/// Read every counter in `opened`, stopping them if `stop`,
+/// and sum each event's counts over the CPUs or threads
+/// sharing a row. Groups that didn't open or can't be read
+/// count nothing, the latter are reported to `warnings`.
+fn read_rows(
+    opened: &[(Option<String>, Vec<Opened>)],
+    groups: &[Vec<StatEvent>],
+    stop: bool,
+    warnings: &mut Warnings,
+) -> Vec<Row> {
+    let mut rows: Vec<Row> = Vec::new();
+    for (label, target_groups) in opened {
+        let read: Vec<Option<Vec<CounterValue>>> = target_groups
+            .iter()
+            .map(|g| {
+                let g = g.as_ref().ok()?;
+                let values = if stop { g.stop() } else { g.read() };
+                values
+                    .map_err(|e| warnings.warn(g.count_error(e).to_string()))
+                    .ok()
+            })
+            .collect();
+        let supported: Vec<bool> = read.iter().map(Option::is_some).collect();
+        let counts: Vec<Vec<CounterValue>> = read
+            .into_iter()
+            .zip(groups)
+            .map(|(values, events)| {
+                values.unwrap_or_else(|| vec![CounterValue::default(); events.len()])
+            })
+            .collect();
+        match rows.iter_mut().find(|row| &row.label == label) {
+            Some(row) => {
+                row.members += 1;
+                for (total, counts) in row.counts.iter_mut().zip(counts) {
+                    for (total, count) in total.iter_mut().zip(counts) {
+                        *total += count;
+                    }
+                }
+                for (any, ok) in row.supported.iter_mut().zip(supported) {
+                    *any |= ok;
+                }
+            }
+            None => rows.push(Row {
+                label: label.clone(),
+                members: 1,
+                counts,
+                supported,
+            }),
         }
     }
+    rows
+}
+
+/// How much each count in `rows` went up since `earlier`,
+/// an older reading of the same counters.
+fn delta(rows: &[Row], earlier: &[Row]) -> Vec<Row> {
+    rows.iter()
+        .enumerate()
+        .map(|(r, row)| match earlier.get(r) {
+            Some(earlier) => Row {
+                label: row.label.clone(),
+                members: row.members,
+                counts: row
+                    .counts
+                    .iter()
+                    .zip(&earlier.counts)
+                    .map(|(now, then)| now.iter().zip(then).map(|(&n, &t)| n - t).collect())
+                    .collect(),
+                supported: row.supported.clone(),
+            },
+            // Threads first seen this interval.
+            None => row.clone(),
+        })
+        .collect()
+}
+
+/// Unit of the counts of `event`.
+fn unit(event: &StatEvent) -> &'static str {
+    if event.is_software(perf_sw_ids_PERF_COUNT_SW_TASK_CLOCK)
+        || event.is_software(perf_sw_ids_PERF_COUNT_SW_CPU_CLOCK)
+    {
+        "ns"
+    } else {
+        ""
+    }
+}
+
+/// Add the metrics of `defs` that can be worked out to
+/// `row`, the counters of `events` on one row, counted
+/// over `seconds`.
+fn attach_metrics(row: &mut [Counter], events: &[&StatEvent], defs: &[MetricDef], seconds: f64) {
+    let values: Vec<Option<f64>> = row.iter().map(|c| c.scaled.map(|v| v as f64)).collect();
+    for (i, metric) in metrics::compute(defs, events, &values, seconds) {
+        row[i].metrics.push(metric);
+    }
+}
+
+/// The counts in `rows`, counted over `span` and, with `-I`,
+/// read `interval` after counting started. Counts are scaled
+/// up when the PMU was shared.
+fn to_counters(
+    rows: &[Row],
+    groups: &[Vec<StatEvent>],
+    defs: &[MetricDef],
+    aggregation: Aggregation,
+    span: Duration,
+    interval: Option<Duration>,
+) -> Vec<Counter> {
+    let mut counters = Vec::new();
+    for row in rows {
+        let start = counters.len();
+        for ((group, counts), &supported) in groups.iter().zip(&row.counts).zip(&row.supported) {
+            for (event, count) in group.iter().zip(counts) {
+                let scaled = count.scaled();
+                counters.push(Counter {
+                    interval: interval.map(|t| t.as_secs_f64()),
+                    run: None,
+                    aggregate: row.label.clone(),
+                    members: Some(row.members).filter(|_| aggregation.per_cpus()),
+                    event: event.to_string(),
+                    supported,
+                    value: count.value,
+                    scaled,
+                    unit: unit(event),
+                    enabled: count.enabled,
+                    running: count.running,
+                    running_percent: count.running_percent(),
+                    seconds: span.as_secs_f64(),
+                    stddev_percent: None,
+                    min: None,
+                    max: None,
+                    metrics: Vec::new(),
+                });
+            }
+        }
+        let events: Vec<&StatEvent> = groups.iter().flatten().collect();
+        attach_metrics(&mut counters[start..], &events, defs, span.as_secs_f64());
+    }
+    counters
+}
+
+/// Format `cpus` back into a list such as `0,2-4`.
+fn cpu_list(cpus: &[i32]) -> String {
+    let mut ranges: Vec<(i32, i32)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, hi)) if *hi + 1 == cpu => *hi = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(lo, hi)| {
+            if lo == hi {
+                lo.to_string()
+            } else {
+                format!("{}-{}", lo, hi)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+#[test]
+fn event_list_test() {
+    let list: EventList = "{cycles,instructions},task-clock".parse().unwrap();
+    assert_eq!(list.0.len(), 2);
+    assert_eq!(list.0[0].len(), 2);
+    assert_eq!(list.0[1].len(), 1);
+    assert_eq!(
+        EventList::from_str("cycles, { cpu-clock }")
+            .unwrap()
+            .0
+            .len(),
+        2
+    );
+    assert!(EventList::from_str("{cycles,instructions").is_err());
+    assert!(EventList::from_str("{cycles}x").is_err());
+    assert!(EventList::from_str("cycles,").is_err());
+    assert!(EventList::from_str("{}").is_err());
+    let list = EventList::from_str("{cpu-clock,r1a},task-clock").unwrap();
+    assert_eq!(list.0[0][1].name, "r1a");
+    assert_eq!(cpu_list(&[0, 2, 3, 4, 7]), "0,2-4,7");
+    assert!(CpuList::from_str("0").is_ok());
+    assert!(CpuList::from_str("100000").is_err());
+    assert!(CpuList::from_str("1-0").is_err());
+    assert_eq!(
+        split_events("cpu/event=1,umask=2/,{a,b},c"),
+        vec!["cpu/event=1,umask=2/", "{a,b}", "c"]
+    );
 }
@@ -0,0 +1,351 @@
+//! Metrics derived from counts, e.g. instructions per cycle.
+//!
+//! A metric is an arithmetic expression over events, such
+//! as `instructions / cycles`. Event names may contain `-`,
+//! so subtraction needs spaces around it, and specifiers
+//! with other punctuation go in quotes: `'cpu/event=0x3c/'`.
+//! `duration_time` is how long counting took in nanoseconds.
+//! Besides the built in metrics, more can be defined in a
+//! file with one `name = expression` per line.
+
+use super::output::Metric;
+use super::spec::StatEvent;
+use crate::utils::ParseError;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Built in metrics: name, label and expression.
+const BUILTIN: &[(&str, &str, &str)] = &[
+    ("cpu-utilized", "CPU utilized", "task-clock / duration_time"),
+    ("ghz", "GHz", "cycles / task-clock"),
+    ("ipc", "insn per cycle", "instructions / cycles"),
+    (
+        "cs-per-sec",
+        "context switches per second",
+        "context-switches / duration_time * 1000000000",
+    ),
+    (
+        "l1d-read-miss-ratio",
+        "L1D read miss ratio",
+        "L1D-cache-read-misses / L1D-cache-reads",
+    ),
+    (
+        "branch-miss-ratio",
+        "branch miss ratio",
+        "branch-misses / branches",
+    ),
+    (
+        "cache-miss-ratio",
+        "cache miss ratio",
+        "cache-misses / cache-references",
+    ),
+];
+
+/// A parsed metric expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Event(StatEvent),
+    /// `duration_time`, in nanoseconds.
+    Duration,
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, char, Box<Expr>),
+}
+
+/// A named metric.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricDef {
+    /// What `-M` selects it by.
+    pub name: String,
+    /// What it is shown as.
+    pub label: String,
+    pub expr: Expr,
+    /// The table it is shown in, if not on its own line.
+    pub table: Option<&'static str>,
+}
+
+/// True if `a` and `b` open the same counter,
+/// however they were spelled.
+fn same_event(a: &StatEvent, b: &StatEvent) -> bool {
+    (a.type_, a.config, a.config1, a.config2) == (b.type_, b.config, b.config1, b.config2)
+        && a.effective_modifiers() == b.effective_modifiers()
+}
+
+/// Characters an unquoted event name is made of.
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_-.:".contains(c)
+}
+
+/// A recursive descent parser over an expression's text.
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        self.rest = self.rest.trim_start();
+        self.rest.chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.rest = &self.rest[1..];
+            true
+        } else {
+            false
+        }
+    }
+
+    /// `term (('+' | '-') term)*`
+    fn expr(&mut self) -> Option<Expr> {
+        let mut left = self.term()?;
+        while let Some(op) = self.peek().filter(|c| "+-".contains(*c)) {
+            self.eat(op);
+            left = Expr::Binary(Box::new(left), op, Box::new(self.term()?));
+        }
+        Some(left)
+    }
+
+    /// `unary (('*' | '/') unary)*`
+    fn term(&mut self) -> Option<Expr> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.peek().filter(|c| "*/".contains(*c)) {
+            self.eat(op);
+            left = Expr::Binary(Box::new(left), op, Box::new(self.unary()?));
+        }
+        Some(left)
+    }
+
+    /// `'-' unary | number | event | '(' expr ')'`
+    fn unary(&mut self) -> Option<Expr> {
+        if self.eat('-') {
+            return Some(Expr::Neg(Box::new(self.unary()?)));
+        }
+        if self.eat('(') {
+            let inner = self.expr()?;
+            return Some(inner).filter(|_| self.eat(')'));
+        }
+        if self.eat('\'') {
+            let end = self.rest.find('\'')?;
+            let name = &self.rest[..end];
+            self.rest = &self.rest[end + 1..];
+            return name.parse().ok().map(Expr::Event);
+        }
+        let c = self.peek()?;
+        if c.is_ascii_digit() || c == '.' {
+            let end = self
+                .rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(self.rest.len());
+            let number = self.rest[..end].parse().ok()?;
+            self.rest = &self.rest[end..];
+            return Some(Expr::Number(number));
+        }
+        let end = self
+            .rest
+            .find(|c| !is_name_char(c))
+            .unwrap_or(self.rest.len());
+        let name = &self.rest[..end];
+        self.rest = &self.rest[end..];
+        match name {
+            "" => None,
+            "duration_time" => Some(Expr::Duration),
+            _ => name.parse().ok().map(Expr::Event),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser { rest: s };
+        match parser.expr() {
+            Some(expr) if parser.peek().is_none() => Ok(expr),
+            _ => Err(ParseError::InvalidMetric(s.to_string())),
+        }
+    }
+}
+
+impl Expr {
+    /// The events the expression reads, in order.
+    pub fn events(&self) -> Vec<&StatEvent> {
+        match self {
+            Expr::Event(event) => vec![event],
+            Expr::Neg(inner) => inner.events(),
+            Expr::Binary(left, _, right) => {
+                let mut events = left.events();
+                events.extend(right.events());
+                events
+            }
+            Expr::Number(_) | Expr::Duration => Vec::new(),
+        }
+    }
+
+    /// The value of the expression given the `value`
+    /// of each event. `None` if one is missing or it
+    /// divides by zero.
+    fn eval(&self, value: &dyn Fn(&StatEvent) -> Option<f64>, duration_ns: f64) -> Option<f64> {
+        match self {
+            Expr::Number(n) => Some(*n),
+            Expr::Event(event) => value(event),
+            Expr::Duration => Some(duration_ns),
+            Expr::Neg(inner) => inner.eval(value, duration_ns).map(|v| -v),
+            Expr::Binary(left, op, right) => {
+                let left = left.eval(value, duration_ns)?;
+                let right = right.eval(value, duration_ns)?;
+                match op {
+                    '+' => Some(left + right),
+                    '-' => Some(left - right),
+                    '*' => Some(left * right),
+                    _ if right == 0.0 => None,
+                    _ => Some(left / right),
+                }
+            }
+        }
+    }
+}
+
+/// The built in metrics.
+pub fn builtin() -> Vec<MetricDef> {
+    BUILTIN
+        .iter()
+        .map(|(name, label, expr)| MetricDef {
+            name: name.to_string(),
+            label: label.to_string(),
+            expr: expr.parse().unwrap(),
+            table: None,
+        })
+        .collect()
+}
+
+/// Metrics defined in a file, one `name = expression` per
+/// line. Blank lines and lines starting with `#` are skipped.
+#[derive(Debug, Clone)]
+pub struct MetricFile(pub Vec<MetricDef>);
+
+impl MetricFile {
+    /// Parse the contents of a metric file.
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        text.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let invalid = || ParseError::InvalidMetric(line.to_string());
+                let (name, expr) = line.split_once('=').ok_or_else(invalid)?;
+                let name = name.trim();
+                if name.is_empty() || name.contains(char::is_whitespace) {
+                    return Err(invalid());
+                }
+                Ok(MetricDef {
+                    name: name.to_string(),
+                    label: name.to_string(),
+                    expr: expr.parse()?,
+                    table: None,
+                })
+            })
+            .collect::<Result<_, _>>()
+            .map(MetricFile)
+    }
+
+    /// Where metrics are read from unless `--metric-file`
+    /// says otherwise, e.g. `~/.config/ruperf/metrics`.
+    pub fn default_path() -> Option<PathBuf> {
+        directories_next::ProjectDirs::from("rs", "ruperf", "ruperf")
+            .map(|dirs| dirs.config_dir().join("metrics"))
+    }
+}
+
+/// Reads the metric file at the given path.
+impl FromStr for MetricFile {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = fs::read_to_string(s).map_err(|_| ParseError::MetricFile(s.to_string()))?;
+        MetricFile::parse(&text)
+    }
+}
+
+/// The metrics of `defs` that can be worked out from
+/// `values`, the scaled counts of `events` over `seconds`.
+/// Each comes with the index of the event it is shown
+/// with, the first one its expression reads.
+pub fn compute(
+    defs: &[MetricDef],
+    events: &[&StatEvent],
+    values: &[Option<f64>],
+    seconds: f64,
+) -> Vec<(usize, Metric)> {
+    let index = |event: &StatEvent| events.iter().position(|e| same_event(e, event));
+    let value = |event: &StatEvent| values[index(event)?];
+    defs.iter()
+        .filter_map(|def| {
+            let shown_with = index(def.expr.events().first()?)?;
+            let value = def.expr.eval(&value, seconds * 1_000_000_000.0)?;
+            let metric = Metric {
+                name: def.label.clone(),
+                value,
+                table: def.table,
+            };
+            Some((shown_with, metric)).filter(|_| value.is_finite())
+        })
+        .collect()
+}
+
+/// The events `def` needs that aren't in `events`.
+pub fn missing_events(def: &MetricDef, events: &[&StatEvent]) -> Vec<StatEvent> {
+    let mut missing: Vec<StatEvent> = Vec::new();
+    for event in def.expr.events() {
+        if !events
+            .iter()
+            .copied()
+            .chain(&missing)
+            .any(|e| same_event(e, event))
+        {
+            missing.push(event.clone());
+        }
+    }
+    missing
+}
+
+#[cfg(test)]
+#[test]
+fn metrics_test() {
+    let expr: Expr = "(instructions - cycles) / -2 * 'cpu-clock:k'"
+        .parse()
+        .unwrap();
+    let names: Vec<String> = expr.events().iter().map(|e| e.name.clone()).collect();
+    assert_eq!(names, vec!["instructions", "cycles", "cpu-clock"]);
+    assert!("cycles /".parse::<Expr>().is_err());
+    assert!("(cycles".parse::<Expr>().is_err());
+    assert!("nonsense-event".parse::<Expr>().is_err());
+
+    let file = MetricFile::parse("# comment\n\nfaults-per-cs = page-faults / cs\n").unwrap();
+    assert_eq!(file.0[0].name, "faults-per-cs");
+    assert!(MetricFile::parse("bad name = cycles").is_err());
+    assert!(MetricFile::parse("cycles").is_err());
+
+    let cycles = StatEvent::named("cycles");
+    let instructions = StatEvent::named("instructions");
+    let cs = StatEvent::named("context-switches");
+    let events = [&instructions, &cycles, &cs];
+    let values = [Some(300.0), Some(100.0), Some(0.0)];
+    let mut defs = builtin();
+    defs.extend(file.0);
+    let metrics = compute(&defs, &events, &values, 2.0);
+    let shown: Vec<(usize, &str, f64)> = metrics
+        .iter()
+        .map(|(i, m)| (*i, m.name.as_str(), m.value))
+        .collect();
+    assert_eq!(
+        shown,
+        vec![
+            (0, "insn per cycle", 3.0),
+            (2, "context switches per second", 0.0)
+        ]
+    );
+
+    let ipc = defs.iter().find(|d| d.name == "ipc").unwrap();
+    assert!(missing_events(ipc, &events).is_empty());
+    let missing = missing_events(ipc, &[&StatEvent::named("cpu-cycles")]);
+    assert_eq!(missing, vec![instructions]);
+}
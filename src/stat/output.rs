@@ -0,0 +1,541 @@
+//! Writing `ruperf stat` results, for people or as
+//! CSV (`-x`) or JSON (`--json`) for other programs.
+//!
+//! Both machine readable formats have one line per
+//! counter reading, with the fields of [`Counter`].
+//! CSV starts with a line naming the columns, every
+//! line has all of them. The metrics of a counter
+//! follow it on lines of their own, with the metric
+//! name as the event, `metric` as the unit and the
+//! metric in the value column. JSON lines are objects.
+
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// A value derived from counts, e.g. CPUs utilized.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Metric {
+    pub name: String,
+    pub value: f64,
+    /// The table people are shown it in, if not on its own line.
+    #[serde(skip)]
+    pub table: Option<&'static str>,
+}
+
+/// One reading of one event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Counter {
+    /// With `-I`, seconds since counting started.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<f64>,
+    /// With `-r`, which run this is, from 1.
+    /// Absent in the summary of every run.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run: Option<usize>,
+    /// The CPU, core, socket or thread counted,
+    /// absent when everything is counted together.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<String>,
+    /// How many CPUs a core or socket has.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub members: Option<usize>,
+    pub event: String,
+    /// False if the event couldn't be opened.
+    pub supported: bool,
+    /// The count as read from the kernel.
+    pub value: u64,
+    /// The count scaled up for the time the event
+    /// wasn't on the PMU, `None` if it never was.
+    pub scaled: Option<u64>,
+    /// Unit of `value` and `scaled`, empty for plain counts.
+    pub unit: &'static str,
+    /// Nanoseconds the event was enabled.
+    pub enabled: u64,
+    /// Nanoseconds the event was on the PMU.
+    pub running: u64,
+    pub running_percent: f64,
+    /// Seconds counted: the interval, or how long the run took.
+    pub seconds: f64,
+    /// In the summary of repeated runs, the standard
+    /// deviation of `scaled` as a percentage of its mean.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stddev_percent: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<u64>,
+    pub metrics: Vec<Metric>,
+}
+
+/// What the command used, as `wait4` reports it.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    /// Seconds spent in user space.
+    pub user: f64,
+    /// Seconds spent in the kernel.
+    pub sys: f64,
+    /// Largest resident set size, in kilobytes.
+    pub max_rss_kb: u64,
+    pub minor_faults: u64,
+    pub major_faults: u64,
+}
+
+/// CSV column names, in the order they are written.
+const CSV_COLUMNS: &[&str] = &[
+    "interval",
+    "run",
+    "aggregate",
+    "members",
+    "event",
+    "supported",
+    "value",
+    "scaled",
+    "unit",
+    "enabled",
+    "running",
+    "running_percent",
+    "seconds",
+    "stddev_percent",
+    "min",
+    "max",
+];
+
+/// Unit of the CSV lines holding metrics.
+const METRIC_UNIT: &str = "metric";
+
+/// How results are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Format {
+    Human,
+    /// CSV with the given separator.
+    Csv(String),
+    Json,
+}
+
+/// Where and how results are written.
+pub struct Output {
+    format: Format,
+    out: Box<dyn Write>,
+    /// Set once the CSV column names are written.
+    started: bool,
+}
+
+/// Quote `field` if it contains `sep`, a quote or a newline.
+fn csv_field(field: &str, sep: &str) -> String {
+    if field.contains(sep) || field.contains('"') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Text of an optional CSV field, empty if `None`.
+fn or_empty<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map_or(String::new(), T::to_string)
+}
+
+impl Counter {
+    /// The counter as CSV lines of fields in [`CSV_COLUMNS`]
+    /// order, its metrics on the lines after its own.
+    fn csv_lines(&self) -> Vec<Vec<String>> {
+        let interval = self.interval.map_or(String::new(), |t| format!("{:.9}", t));
+        let seconds = format!("{:.9}", self.seconds);
+        let mut lines = vec![vec![
+            interval.clone(),
+            or_empty(&self.run),
+            or_empty(&self.aggregate),
+            or_empty(&self.members),
+            self.event.clone(),
+            self.supported.to_string(),
+            self.value.to_string(),
+            or_empty(&self.scaled),
+            self.unit.to_string(),
+            self.enabled.to_string(),
+            self.running.to_string(),
+            format!("{:.2}", self.running_percent),
+            seconds.clone(),
+            self.stddev_percent
+                .map_or(String::new(), |p| format!("{:.2}", p)),
+            or_empty(&self.min),
+            or_empty(&self.max),
+        ]];
+        for metric in &self.metrics {
+            lines.push(vec![
+                interval.clone(),
+                or_empty(&self.run),
+                or_empty(&self.aggregate),
+                or_empty(&self.members),
+                metric.name.clone(),
+                true.to_string(),
+                format!("{:.3}", metric.value),
+                String::new(),
+                METRIC_UNIT.to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+                seconds.clone(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ]);
+        }
+        lines
+    }
+
+    /// Lines describing the counter for people, after `prefix`.
+    fn human_lines(&self, prefix: &str) -> Vec<String> {
+        let running = format!("({:.2}%)", self.running_percent);
+        let spread = match (self.stddev_percent, self.min, self.max) {
+            (Some(stddev), Some(min), Some(max)) if self.unit == "ns" => format!(
+                " ( +- {:.2}% ) min {:.2} max {:.2}",
+                stddev,
+                min as f64 / 1_000_000.0,
+                max as f64 / 1_000_000.0
+            ),
+            (Some(stddev), Some(min), Some(max)) => {
+                format!(" ( +- {:.2}% ) min {} max {}", stddev, min, max)
+            }
+            _ => String::new(),
+        };
+        let mut lines = vec![match self.scaled {
+            None if !self.supported => {
+                format!("{} Number of {}: <not supported>", prefix, self.event)
+            }
+            None => format!(
+                "{} Number of {}: <not counted> {}",
+                prefix, self.event, running
+            ),
+            Some(value) if self.unit == "ns" => format!(
+                "{} {:.2} msec {}{} {}",
+                prefix,
+                value as f64 / 1_000_000.0,
+                self.event,
+                spread,
+                running
+            ),
+            Some(value) => format!(
+                "{} Number of {}: {}{} {}",
+                prefix, self.event, value, spread, running
+            ),
+        }];
+        for metric in self.metrics.iter().filter(|m| m.table.is_none()) {
+            lines.push(format!("{} {}: {:.3}", prefix, metric.name, metric.value));
+        }
+        lines
+    }
+}
+
+/// Metrics shown side by side as percentages, a row
+/// for each prefix they were computed for.
+struct Table {
+    title: &'static str,
+    columns: Vec<String>,
+    rows: Vec<(String, Vec<Option<f64>>)>,
+}
+
+impl Table {
+    fn add(&mut self, row: &str, column: &str, value: f64) {
+        let c = match self.columns.iter().position(|c| c == column) {
+            Some(c) => c,
+            None => {
+                self.columns.push(column.to_string());
+                self.columns.len() - 1
+            }
+        };
+        let r = match self.rows.iter().position(|(r, _)| r == row) {
+            Some(r) => r,
+            None => {
+                self.rows.push((row.to_string(), Vec::new()));
+                self.rows.len() - 1
+            }
+        };
+        let values = &mut self.rows[r].1;
+        values.resize(values.len().max(c + 1), None);
+        values[c] = Some(value);
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        let width = self.rows.iter().map(|(r, _)| r.len()).max().unwrap_or(0);
+        let mut header = format!("{:w$}", "", w = width);
+        for column in &self.columns {
+            header.push_str(&format!("  {:>7}", column));
+        }
+        writeln!(out, "\n {}:\n{}", self.title, header)?;
+        for (row, values) in &self.rows {
+            let mut line = format!("{:<w$}", row, w = width);
+            for (c, column) in self.columns.iter().enumerate() {
+                let value = match values.get(c).copied().flatten() {
+                    Some(v) => format!("{:.1}%", v * 100.0),
+                    None => "-".to_string(),
+                };
+                line.push_str(&format!("  {:>w$}", value, w = column.len().max(7)));
+            }
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Output {
+    /// Write `format` to the file at `path`, or to stdout.
+    pub fn new(format: Format, path: Option<&Path>) -> io::Result<Self> {
+        let out: Box<dyn Write> = match path {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout()),
+        };
+        Ok(Output {
+            format,
+            out,
+            started: false,
+        })
+    }
+
+    /// True if results are written for people.
+    pub fn is_human(&self) -> bool {
+        self.format == Format::Human
+    }
+
+    /// Say what is being counted. Only people need
+    /// telling, CSV gets its column names instead.
+    pub fn header(&mut self, header: &str) -> io::Result<()> {
+        match &self.format {
+            Format::Human => writeln!(self.out, "{}", header),
+            Format::Csv(sep) if !self.started => {
+                self.started = true;
+                writeln!(self.out, "{}", CSV_COLUMNS.join(sep))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Write readings taken at the same time. For people,
+    /// each line starts with the interval and the aggregate,
+    /// padded to line up with the others.
+    pub fn counters(&mut self, counters: &[Counter]) -> io::Result<()> {
+        match &self.format {
+            Format::Human => {
+                let width = counters
+                    .iter()
+                    .filter_map(|c| c.aggregate.as_ref().map(|a| a.len()))
+                    .max()
+                    .unwrap_or(0);
+                let mut tables: Vec<Table> = Vec::new();
+                for counter in counters {
+                    let mut prefix = Vec::new();
+                    if let Some(time) = counter.interval {
+                        prefix.push(format!("{:>14.9}", time));
+                    }
+                    match (&counter.aggregate, counter.members) {
+                        (Some(label), Some(n)) => {
+                            prefix.push(format!("{:<w$} {:>3} CPUs", label, n, w = width))
+                        }
+                        (Some(label), None) => prefix.push(format!("{:<w$}", label, w = width)),
+                        (None, _) => {}
+                    }
+                    let prefix = prefix.join(" ");
+                    for line in counter.human_lines(&prefix) {
+                        writeln!(self.out, "{}", line)?;
+                    }
+                    for metric in &counter.metrics {
+                        let title = match metric.table {
+                            Some(title) => title,
+                            None => continue,
+                        };
+                        let table = match tables.iter().position(|t| t.title == title) {
+                            Some(t) => &mut tables[t],
+                            None => {
+                                tables.push(Table {
+                                    title,
+                                    columns: Vec::new(),
+                                    rows: Vec::new(),
+                                });
+                                tables.last_mut().unwrap()
+                            }
+                        };
+                        table.add(&prefix, &metric.name, metric.value);
+                    }
+                }
+                for table in &tables {
+                    table.write(&mut self.out)?;
+                }
+            }
+            Format::Csv(sep) => {
+                for fields in counters.iter().flat_map(Counter::csv_lines) {
+                    let fields: Vec<String> = fields.iter().map(|f| csv_field(f, sep)).collect();
+                    writeln!(self.out, "{}", fields.join(sep))?;
+                }
+            }
+            Format::Json => {
+                for counter in counters {
+                    writeln!(self.out, "{}", serde_json::to_string(counter).unwrap())?;
+                }
+            }
+        }
+        self.out.flush()
+    }
+
+    /// Say how long counting took, with its relative
+    /// standard deviation over repeated runs, and what
+    /// the command used. Machine readable formats have
+    /// the time in every counter.
+    pub fn elapsed(
+        &mut self,
+        seconds: f64,
+        spread: Option<(f64, f64)>,
+        usage: Option<Usage>,
+    ) -> io::Result<()> {
+        if !self.is_human() {
+            return Ok(());
+        }
+        match spread {
+            None => writeln!(self.out, "\n {:.9} seconds time elapsed", seconds)?,
+            Some((stddev, percent)) => writeln!(
+                self.out,
+                "\n {:.9} +- {:.9} seconds time elapsed ( +- {:.2}% )",
+                seconds, stddev, percent
+            )?,
+        }
+        if let Some(usage) = usage {
+            writeln!(self.out, "\n {:.9} seconds user", usage.user)?;
+            writeln!(self.out, " {:.9} seconds sys", usage.sys)?;
+            writeln!(
+                self.out,
+                "\n {} KB max resident set size\n {} minor and {} major page faults",
+                usage.max_rss_kb, usage.minor_faults, usage.major_faults
+            )?;
+        }
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn output_test() {
+    let counter = Counter {
+        interval: None,
+        run: Some(2),
+        aggregate: Some("CPU0".to_string()),
+        members: None,
+        event: "cpu/event=0x3c,umask=0x0/".to_string(),
+        supported: true,
+        value: 10,
+        scaled: Some(20),
+        unit: "",
+        enabled: 200,
+        running: 100,
+        running_percent: 50.0,
+        seconds: 1.0,
+        stddev_percent: None,
+        min: None,
+        max: None,
+        metrics: vec![Metric {
+            name: "CPU utilized".to_string(),
+            value: 0.5,
+            table: None,
+        }],
+    };
+    let lines = counter.csv_lines();
+    let fields = &lines[0];
+    assert_eq!(fields[4], counter.event);
+    assert_eq!(lines[1][4], "CPU utilized");
+    assert_eq!(lines[1][6], "0.500");
+    assert_eq!(lines[1][8], METRIC_UNIT);
+    assert_eq!(csv_field(&fields[4], ","), "\"cpu/event=0x3c,umask=0x0/\"");
+    assert_eq!(csv_field("say \"hi\"", ";"), "\"say \"\"hi\"\"\"");
+    assert_eq!(csv_field("cycles:u", ","), "cycles:u");
+
+    let json = serde_json::to_value(&counter).unwrap();
+    assert_eq!(json["run"], 2);
+    assert_eq!(json["scaled"], 20);
+    assert!(json.get("interval").is_none());
+    assert_eq!(json["metrics"][0]["name"], "CPU utilized");
+
+    let lines = counter.human_lines("CPU0");
+    assert_eq!(
+        lines,
+        vec![
+            "CPU0 Number of cpu/event=0x3c,umask=0x0/: 20 (50.00%)",
+            "CPU0 CPU utilized: 0.500"
+        ]
+    );
+    let unsupported = Counter {
+        supported: false,
+        scaled: None,
+        metrics: Vec::new(),
+        ..counter
+    };
+    assert_eq!(
+        unsupported.human_lines(""),
+        vec![" Number of cpu/event=0x3c,umask=0x0/: <not supported>"]
+    );
+
+    let mut table = Table {
+        title: "top-down level 1",
+        columns: Vec::new(),
+        rows: Vec::new(),
+    };
+    table.add("CPU0", "retiring", 0.25);
+    table.add("CPU1", "backend bound", 0.5);
+    let mut out = Vec::new();
+    table.write(&mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\n top-down level 1:\n      retiring  backend bound\n\
+         CPU0     25.0%              -\n\
+         CPU1         -          50.0%\n"
+    );
+}
+
+#[test]
+fn csv_schema_test() {
+    let metric = |name: &str| Metric {
+        name: name.to_string(),
+        value: 1.5,
+        table: None,
+    };
+    let counter = |metrics: Vec<Metric>| Counter {
+        interval: Some(1.0),
+        run: None,
+        aggregate: None,
+        members: None,
+        event: "cycles".to_string(),
+        supported: true,
+        value: 10,
+        scaled: Some(10),
+        unit: "",
+        enabled: 100,
+        running: 100,
+        running_percent: 100.0,
+        seconds: 1.0,
+        stddev_percent: None,
+        min: None,
+        max: None,
+        metrics,
+    };
+    let counters = vec![
+        counter(Vec::new()),
+        counter(vec![metric("insn per cycle")]),
+        counter(vec![
+            metric("retiring"),
+            metric("bad speculation"),
+            metric("backend bound"),
+        ]),
+    ];
+    let path = std::env::temp_dir().join(format!("ruperf-csv-{}", std::process::id()));
+    let mut output = Output::new(Format::Csv(";".to_string()), Some(path.as_path())).unwrap();
+    output.header("").unwrap();
+    output.counters(&counters).unwrap();
+    drop(output);
+    let csv = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], CSV_COLUMNS.join(";"));
+    assert_eq!(lines.len(), 1 + 3 + 4);
+    for line in &lines {
+        assert_eq!(line.split(';').count(), CSV_COLUMNS.len(), "{}", line);
+    }
+    assert!(lines[3].starts_with("1.000000000;;;;insn per cycle;true;1.500;;metric;"));
+}
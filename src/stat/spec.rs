@@ -0,0 +1,663 @@
+//! Event specifiers, as given to `-e`.
+//!
+//! An event is one of the generic hardware or software
+//! events (`cycles`, `page-faults`), a hardware cache event
+//! (`LLC-load-misses`), a raw PMU config (`r01c2`), or a PMU
+//! event built from its sysfs format fields
+//! (`cpu/event=0x3c,umask=0x00/`), or a tracepoint
+//! (`sched:sched_switch`). Any of them may end in
+//! `:` and modifiers, e.g. `cycles:k` or `cycles:uppp`.
+//! PMU events may also have them right after the
+//! closing `/`, as perf writes them: `cpu/event=0x3c/u`.
+
+use crate::bindings::*;
+use crate::event::cpu::{online_cpus, parse_cpu_list};
+use crate::event::tracepoint::{find_tracepoint, tracepoint_id, Format, TRACEFS_ROOTS};
+use crate::utils::ParseError;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Where the kernel lists the PMUs and their formats.
+pub const SYSFS_PMUS: &str = "/sys/bus/event_source/devices";
+
+/// Generic hardware events by name.
+pub const HARDWARE: &[(&str, u32)] = &[
+    ("cycles", perf_hw_id_PERF_COUNT_HW_CPU_CYCLES),
+    ("cpu-cycles", perf_hw_id_PERF_COUNT_HW_CPU_CYCLES),
+    ("instructions", perf_hw_id_PERF_COUNT_HW_INSTRUCTIONS),
+    (
+        "cache-references",
+        perf_hw_id_PERF_COUNT_HW_CACHE_REFERENCES,
+    ),
+    ("cache-misses", perf_hw_id_PERF_COUNT_HW_CACHE_MISSES),
+    ("branches", perf_hw_id_PERF_COUNT_HW_BRANCH_INSTRUCTIONS),
+    (
+        "branch-instructions",
+        perf_hw_id_PERF_COUNT_HW_BRANCH_INSTRUCTIONS,
+    ),
+    ("branch-misses", perf_hw_id_PERF_COUNT_HW_BRANCH_MISSES),
+    ("bus-cycles", perf_hw_id_PERF_COUNT_HW_BUS_CYCLES),
+    (
+        "stalled-cycles-frontend",
+        perf_hw_id_PERF_COUNT_HW_STALLED_CYCLES_FRONTEND,
+    ),
+    (
+        "idle-cycles-frontend",
+        perf_hw_id_PERF_COUNT_HW_STALLED_CYCLES_FRONTEND,
+    ),
+    (
+        "stalled-cycles-backend",
+        perf_hw_id_PERF_COUNT_HW_STALLED_CYCLES_BACKEND,
+    ),
+    (
+        "idle-cycles-backend",
+        perf_hw_id_PERF_COUNT_HW_STALLED_CYCLES_BACKEND,
+    ),
+    ("ref-cycles", perf_hw_id_PERF_COUNT_HW_REF_CPU_CYCLES),
+];
+
+/// Software events by name. `cgroup-switches` is
+/// newer than the bindings, so its id is spelled out.
+pub const SOFTWARE: &[(&str, u32)] = &[
+    ("cpu-clock", perf_sw_ids_PERF_COUNT_SW_CPU_CLOCK),
+    ("task-clock", perf_sw_ids_PERF_COUNT_SW_TASK_CLOCK),
+    ("page-faults", perf_sw_ids_PERF_COUNT_SW_PAGE_FAULTS),
+    ("faults", perf_sw_ids_PERF_COUNT_SW_PAGE_FAULTS),
+    (
+        "context-switches",
+        perf_sw_ids_PERF_COUNT_SW_CONTEXT_SWITCHES,
+    ),
+    ("cs", perf_sw_ids_PERF_COUNT_SW_CONTEXT_SWITCHES),
+    ("cpu-migrations", perf_sw_ids_PERF_COUNT_SW_CPU_MIGRATIONS),
+    ("migrations", perf_sw_ids_PERF_COUNT_SW_CPU_MIGRATIONS),
+    ("minor-faults", perf_sw_ids_PERF_COUNT_SW_PAGE_FAULTS_MIN),
+    ("major-faults", perf_sw_ids_PERF_COUNT_SW_PAGE_FAULTS_MAJ),
+    (
+        "alignment-faults",
+        perf_sw_ids_PERF_COUNT_SW_ALIGNMENT_FAULTS,
+    ),
+    (
+        "emulation-faults",
+        perf_sw_ids_PERF_COUNT_SW_EMULATION_FAULTS,
+    ),
+    ("dummy", perf_sw_ids_PERF_COUNT_SW_DUMMY),
+    ("bpf-output", perf_sw_ids_PERF_COUNT_SW_BPF_OUTPUT),
+    ("cgroup-switches", 11),
+];
+
+/// Hardware caches by name, lowercase. Includes
+/// perf's aliases and the older `L1D-cache` spelling.
+const CACHES: &[(&str, u32)] = &[
+    ("l1-dcache", perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1D),
+    ("l1d-cache", perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1D),
+    ("l1-d", perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1D),
+    ("l1d", perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1D),
+    ("l1-data", perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1D),
+    ("l1-icache", perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1I),
+    ("l1i-cache", perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1I),
+    ("l1-i", perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1I),
+    ("l1i", perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1I),
+    ("l1-instruction", perf_hw_cache_id_PERF_COUNT_HW_CACHE_L1I),
+    ("llc", perf_hw_cache_id_PERF_COUNT_HW_CACHE_LL),
+    ("l2", perf_hw_cache_id_PERF_COUNT_HW_CACHE_LL),
+    ("dtlb", perf_hw_cache_id_PERF_COUNT_HW_CACHE_DTLB),
+    ("d-tlb", perf_hw_cache_id_PERF_COUNT_HW_CACHE_DTLB),
+    ("data-tlb", perf_hw_cache_id_PERF_COUNT_HW_CACHE_DTLB),
+    ("itlb", perf_hw_cache_id_PERF_COUNT_HW_CACHE_ITLB),
+    ("i-tlb", perf_hw_cache_id_PERF_COUNT_HW_CACHE_ITLB),
+    ("instruction-tlb", perf_hw_cache_id_PERF_COUNT_HW_CACHE_ITLB),
+    ("branch", perf_hw_cache_id_PERF_COUNT_HW_CACHE_BPU),
+    ("branches", perf_hw_cache_id_PERF_COUNT_HW_CACHE_BPU),
+    ("bpu", perf_hw_cache_id_PERF_COUNT_HW_CACHE_BPU),
+    ("btb", perf_hw_cache_id_PERF_COUNT_HW_CACHE_BPU),
+    ("bpc", perf_hw_cache_id_PERF_COUNT_HW_CACHE_BPU),
+    ("node", perf_hw_cache_id_PERF_COUNT_HW_CACHE_NODE),
+];
+
+/// Cache operations by name.
+const CACHE_OPS: &[(&str, u32)] = &[
+    ("load", perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_READ),
+    ("loads", perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_READ),
+    ("read", perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_READ),
+    ("reads", perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_READ),
+    ("store", perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_WRITE),
+    ("stores", perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_WRITE),
+    ("write", perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_WRITE),
+    ("writes", perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_WRITE),
+    (
+        "prefetch",
+        perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_PREFETCH,
+    ),
+    (
+        "prefetches",
+        perf_hw_cache_op_id_PERF_COUNT_HW_CACHE_OP_PREFETCH,
+    ),
+];
+
+/// Cache operation results by name.
+const CACHE_RESULTS: &[(&str, u32)] = &[
+    (
+        "access",
+        perf_hw_cache_op_result_id_PERF_COUNT_HW_CACHE_RESULT_ACCESS,
+    ),
+    (
+        "accesses",
+        perf_hw_cache_op_result_id_PERF_COUNT_HW_CACHE_RESULT_ACCESS,
+    ),
+    (
+        "refs",
+        perf_hw_cache_op_result_id_PERF_COUNT_HW_CACHE_RESULT_ACCESS,
+    ),
+    (
+        "miss",
+        perf_hw_cache_op_result_id_PERF_COUNT_HW_CACHE_RESULT_MISS,
+    ),
+    (
+        "misses",
+        perf_hw_cache_op_result_id_PERF_COUNT_HW_CACHE_RESULT_MISS,
+    ),
+];
+
+/// Letters allowed after an event's `:`.
+const MODIFIERS: &str = "ukhIGHpS";
+
+/// Modifiers given after an event's `:`, see `perf list`.
+/// If none of `u`, `k` and `h` are given the event's
+/// default privilege levels are counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    /// `u`, count user space.
+    pub user: bool,
+    /// `k`, count the kernel.
+    pub kernel: bool,
+    /// `h`, count the hypervisor.
+    pub hv: bool,
+    /// `I`, don't count while the CPU is idle.
+    pub exclude_idle: bool,
+    /// `G`, only count in guests.
+    pub guest: bool,
+    /// `H`, only count on the host.
+    pub host: bool,
+    /// One `p` per level of skid constraint, at most 3.
+    pub precise: u8,
+    /// `S`, read the counter into each sample.
+    pub sample_read: bool,
+}
+
+impl Modifiers {
+    /// True if any of `u`, `k` or `h` were given.
+    pub fn has_privileges(&self) -> bool {
+        self.user || self.kernel || self.hv
+    }
+}
+
+impl FromStr for Modifiers {
+    type Err = ParseError;
+    /// Parse the letters after `:`, e.g. `ukpp`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |why| ParseError::InvalidModifiers(s.to_string(), why);
+        let mut m = Modifiers::default();
+        for c in s.chars() {
+            let flag = match c {
+                'u' => &mut m.user,
+                'k' => &mut m.kernel,
+                'h' => &mut m.hv,
+                'I' => &mut m.exclude_idle,
+                'G' => &mut m.guest,
+                'H' => &mut m.host,
+                'S' => &mut m.sample_read,
+                'p' if m.precise == 3 => return Err(invalid("at most 3 p")),
+                'p' => {
+                    m.precise += 1;
+                    continue;
+                }
+                _ => return Err(invalid("unknown modifier")),
+            };
+            if *flag {
+                return Err(invalid("modifier given twice"));
+            }
+            *flag = true;
+        }
+        if m.guest && m.host {
+            return Err(invalid("G and H exclude each other"));
+        }
+        Ok(m)
+    }
+}
+
+impl fmt::Display for Modifiers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = [
+            (self.user, "u"),
+            (self.kernel, "k"),
+            (self.hv, "h"),
+            (self.exclude_idle, "I"),
+            (self.guest, "G"),
+            (self.host, "H"),
+        ];
+        for (_, letter) in flags.iter().filter(|(set, _)| *set) {
+            write!(f, "{}", letter)?;
+        }
+        write!(f, "{}", "p".repeat(self.precise as usize))?;
+        if self.sample_read {
+            write!(f, "S")?;
+        }
+        Ok(())
+    }
+}
+
+/// A parsed event specifier, holding everything
+/// `event_open` needs to fill in a `perf_event_attr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatEvent {
+    /// The specifier as written without its
+    /// modifiers, e.g. `cycles` or `r01c2`.
+    pub name: String,
+    /// A `PERF_TYPE_*` id, or the dynamic type of a PMU.
+    pub type_: u32,
+    pub config: u64,
+    pub config1: u64,
+    pub config2: u64,
+    /// Modifiers as given, see `effective_modifiers()`.
+    pub modifiers: Modifiers,
+    /// True for PMUs outside the CPU cores, see `is_uncore()`.
+    pub uncore: bool,
+}
+
+impl StatEvent {
+    /// An event known to exist, e.g. `StatEvent::named("cycles")`.
+    /// Panics if `name` doesn't parse.
+    pub fn named(name: &str) -> Self {
+        name.parse()
+            .unwrap_or_else(|_| panic!("unknown event '{}'", name))
+    }
+
+    fn new(name: &str, type_: u32, config: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            type_,
+            config,
+            config1: 0,
+            config2: 0,
+            modifiers: Modifiers::default(),
+            uncore: false,
+        }
+    }
+
+    /// True if this is the generic hardware event `id`.
+    pub fn is_hardware(&self, id: u32) -> bool {
+        self.type_ == perf_type_id_PERF_TYPE_HARDWARE && self.config == id as u64
+    }
+
+    /// True if this is the software event `id`.
+    pub fn is_software(&self, id: u32) -> bool {
+        self.type_ == perf_type_id_PERF_TYPE_SOFTWARE && self.config == id as u64
+    }
+
+    /// True if this is a tracepoint, named `subsys:event`.
+    pub fn is_tracepoint(&self) -> bool {
+        self.type_ == perf_type_id_PERF_TYPE_TRACEPOINT
+    }
+
+    /// The layout of this tracepoint's raw sample data.
+    pub fn tracepoint_format(&self) -> Option<Format> {
+        let (subsys, event) = self.name.split_once(':').filter(|_| self.is_tracepoint())?;
+        Format::read(&find_tracepoint(TRACEFS_ROOTS, subsys, event)?)
+    }
+
+    /// The modifiers the event is opened with. Without `u`,
+    /// `k` or `h` only user space is counted, except for
+    /// tracepoints, context switches and migrations, which
+    /// happen in the kernel and would be hidden by excluding it.
+    /// Uncore PMUs, such as `power/`, can't tell the levels
+    /// apart and refuse to exclude any, so count them all.
+    pub fn effective_modifiers(&self) -> Modifiers {
+        let mut m = self.modifiers;
+        if !m.has_privileges() && self.uncore {
+            m.user = true;
+            m.kernel = true;
+            m.hv = true;
+        } else if !m.has_privileges() {
+            m.user = true;
+            m.kernel = self.is_tracepoint()
+                || self.is_software(perf_sw_ids_PERF_COUNT_SW_CONTEXT_SWITCHES)
+                || self.is_software(perf_sw_ids_PERF_COUNT_SW_CPU_MIGRATIONS);
+        }
+        m
+    }
+}
+
+/// Shows the name with the effective modifiers, e.g. `cycles:u`.
+impl fmt::Display for StatEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.name, self.effective_modifiers())
+    }
+}
+
+/// Look `name` up in one of the tables above.
+fn lookup(table: &[(&str, u32)], name: &str) -> Option<u32> {
+    table.iter().find(|(n, _)| *n == name).map(|(_, id)| *id)
+}
+
+/// Parse a `<cache>-<op>[-<result>]` name into its
+/// `PERF_TYPE_HW_CACHE` config. Accesses are counted
+/// if no result is given.
+fn cache_config(name: &str) -> Option<u64> {
+    let name = name.to_ascii_lowercase();
+    CACHES.iter().find_map(|(cache, id)| {
+        let rest = name.strip_prefix(cache)?.strip_prefix('-')?;
+        let (op, result) = match rest.split_once('-') {
+            Some((op, result)) => (op, lookup(CACHE_RESULTS, result)?),
+            None => (
+                rest,
+                perf_hw_cache_op_result_id_PERF_COUNT_HW_CACHE_RESULT_ACCESS,
+            ),
+        };
+        let op = lookup(CACHE_OPS, op)?;
+        Some(*id as u64 | (op as u64) << 8 | (result as u64) << 16)
+    })
+}
+
+/// Parse a decimal or `0x` prefixed hex number.
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Store `value` into the bits a sysfs format
+/// file such as `config:0-7,32-35` describes.
+/// Low bits of `value` fill the first range.
+fn set_format_field(event: &mut StatEvent, format: &str, value: u64) -> Option<()> {
+    let (target, ranges) = format.trim().split_once(':')?;
+    let field = match target {
+        "config" => &mut event.config,
+        "config1" => &mut event.config1,
+        "config2" => &mut event.config2,
+        _ => return None,
+    };
+    let mut value = value;
+    for range in ranges.split(',') {
+        let (lo, hi): (u32, u32) = match range.split_once('-') {
+            Some((lo, hi)) => (lo.parse().ok()?, hi.parse().ok()?),
+            None => {
+                let bit = range.parse().ok()?;
+                (bit, bit)
+            }
+        };
+        if hi < lo || hi > 63 {
+            return None;
+        }
+        let width = hi - lo + 1;
+        let mask = u64::MAX >> (64 - width);
+        *field = (*field & !(mask << lo)) | ((value & mask) << lo);
+        value = value.checked_shr(width).unwrap_or(0);
+    }
+    Some(())
+}
+
+/// Apply comma separated `terms`, e.g. `event=0x3c,umask=1,edge`,
+/// using the format and event alias files of the PMU in `dir`.
+fn apply_terms(
+    event: &mut StatEvent,
+    dir: &Path,
+    terms: &str,
+    alias: bool,
+) -> Result<(), ParseError> {
+    let invalid = |term: &str| ParseError::InvalidPmuTerm(term.to_string());
+    for term in terms.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        let (key, value) = match term.split_once('=') {
+            Some((key, value)) => (
+                key.trim(),
+                Some(parse_number(value.trim()).ok_or_else(|| invalid(term))?),
+            ),
+            None => (term, None),
+        };
+        match (key, value) {
+            ("config", Some(v)) => event.config = v,
+            ("config1", Some(v)) => event.config1 = v,
+            ("config2", Some(v)) => event.config2 = v,
+            _ => {
+                if let Ok(format) = fs::read_to_string(dir.join("format").join(key)) {
+                    // Flags such as `edge` are set by naming them.
+                    set_format_field(event, &format, value.unwrap_or(1))
+                        .ok_or_else(|| invalid(term))?;
+                } else if let (false, None, Ok(terms)) = (
+                    alias,
+                    value,
+                    fs::read_to_string(dir.join("events").join(key)),
+                ) {
+                    // A named event, e.g. `cpu/mem-loads/`, is
+                    // shorthand for the terms in its alias file.
+                    apply_terms(event, dir, terms.trim(), true)?;
+                } else {
+                    return Err(invalid(term));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Build the event `pmu/terms/` from the PMU's sysfs files under `root`.
+fn pmu_event(root: &Path, name: &str, pmu: &str, terms: &str) -> Result<StatEvent, ParseError> {
+    let dir = root.join(pmu);
+    let type_ = fs::read_to_string(dir.join("type"))
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .ok_or_else(|| ParseError::UnknownPmu(pmu.to_string()))?;
+    let mut event = StatEvent::new(name, type_, 0);
+    event.uncore = is_uncore(&dir, &online_cpus());
+    apply_terms(&mut event, &dir, terms, false)?;
+    Ok(event)
+}
+
+/// True if the PMU in `dir` is outside the CPU cores. Such
+/// PMUs list the few CPUs to open them on in `cpumask`, while
+/// core PMUs, hybrid and ARM ones included, count on every CPU.
+/// With a single CPU online any `cpumask` is taken as uncore.
+fn is_uncore(dir: &Path, online: &[i32]) -> bool {
+    fs::read_to_string(dir.join("cpumask"))
+        .ok()
+        .and_then(|s| parse_cpu_list(&s))
+        .is_some_and(|cpus| online.len() == 1 || !online.iter().all(|cpu| cpus.contains(cpu)))
+}
+
+/// Parse `s` as an event, resolving PMUs under `root`.
+/// Anything after the last `:` is taken as modifiers if
+/// it could only be modifiers, or if what comes before it
+/// is an event on its own, e.g. `cycles:x`. Otherwise the
+/// `:` belongs to a tracepoint. Whatever follows the closing
+/// `/` of a PMU event is its modifiers.
+pub fn parse_event(s: &str, root: &Path) -> Result<StatEvent, ParseError> {
+    let s = s.trim();
+    if let Some((base, modifiers)) = s.rsplit_once('/') {
+        if base.contains('/') && !modifiers.is_empty() && !modifiers.starts_with(':') {
+            let mut event = parse_base_event(&s[..=base.len()], root)?;
+            event.modifiers = modifiers.parse()?;
+            return Ok(event);
+        }
+    }
+    let (base, modifiers) = match s.rsplit_once(':') {
+        Some((base, modifiers)) if !modifiers.is_empty() => (base, modifiers),
+        _ => return parse_base_event(s, root),
+    };
+    let letters = modifiers.chars().all(|c| MODIFIERS.contains(c));
+    match parse_base_event(base, root) {
+        Ok(mut event) => {
+            event.modifiers = modifiers.parse()?;
+            Ok(event)
+        }
+        Err(_) if !letters => parse_base_event(s, root),
+        Err(err) => Err(err),
+    }
+}
+
+/// Parse `s`, without modifiers, as an event.
+fn parse_base_event(s: &str, root: &Path) -> Result<StatEvent, ParseError> {
+    if let Some((pmu, rest)) = s.split_once('/') {
+        let terms = rest.strip_suffix('/').ok_or(ParseError::InvalidEvent)?;
+        return pmu_event(root, s, pmu, terms);
+    }
+    if let Some((subsys, event)) = s.split_once(':') {
+        let id = find_tracepoint(TRACEFS_ROOTS, subsys, event)
+            .and_then(|dir| tracepoint_id(&dir))
+            .ok_or_else(|| ParseError::UnknownTracepoint(s.to_string()))?;
+        return Ok(StatEvent::new(s, perf_type_id_PERF_TYPE_TRACEPOINT, id));
+    }
+    if let Some(id) = lookup(HARDWARE, s) {
+        return Ok(StatEvent::new(
+            s,
+            perf_type_id_PERF_TYPE_HARDWARE,
+            id as u64,
+        ));
+    }
+    if let Some(id) = lookup(SOFTWARE, s) {
+        return Ok(StatEvent::new(
+            s,
+            perf_type_id_PERF_TYPE_SOFTWARE,
+            id as u64,
+        ));
+    }
+    if let Some(config) = cache_config(s) {
+        return Ok(StatEvent::new(s, perf_type_id_PERF_TYPE_HW_CACHE, config));
+    }
+    if let Some(hex) = s.strip_prefix('r') {
+        if let Ok(config) = u64::from_str_radix(hex, 16) {
+            return Ok(StatEvent::new(s, perf_type_id_PERF_TYPE_RAW, config));
+        }
+    }
+    Err(ParseError::InvalidEvent)
+}
+
+impl FromStr for StatEvent {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_event(s, Path::new(SYSFS_PMUS))
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn parse_event_test() {
+    let cycles = StatEvent::named("cycles");
+    assert!(cycles.is_hardware(perf_hw_id_PERF_COUNT_HW_CPU_CYCLES));
+    assert!(StatEvent::named("cs").is_software(perf_sw_ids_PERF_COUNT_SW_CONTEXT_SWITCHES));
+    assert_eq!(
+        StatEvent::named("ref-cycles").type_,
+        perf_type_id_PERF_TYPE_HARDWARE
+    );
+
+    let llc = StatEvent::named("LLC-load-misses");
+    assert_eq!(llc.type_, perf_type_id_PERF_TYPE_HW_CACHE);
+    assert_eq!(llc.config, 0x10002);
+    assert_eq!(StatEvent::named("L1D-cache-writes").config, 0x100);
+    assert_eq!(StatEvent::named("dTLB-prefetches").config, 0x203);
+    assert_eq!(StatEvent::named("branch-load-misses").config, 0x10005);
+    assert!("L1-dcache-flushes".parse::<StatEvent>().is_err());
+
+    let e = StatEvent::named("cycles:kppp");
+    assert_eq!(e.name, "cycles");
+    assert!(e.modifiers.kernel && !e.modifiers.user);
+    assert_eq!(e.modifiers.precise, 3);
+    assert_eq!(e.to_string(), "cycles:kppp");
+    assert_eq!(StatEvent::named("cycles").to_string(), "cycles:u");
+    assert_eq!(StatEvent::named("cs").to_string(), "cs:uk");
+    assert_eq!(StatEvent::named("cs:u").to_string(), "cs:u");
+    assert!(matches!(
+        "cycles:GH".parse::<StatEvent>(),
+        Err(ParseError::InvalidModifiers(..))
+    ));
+    assert!("cycles:pppp".parse::<StatEvent>().is_err());
+    assert!("cycles:uu".parse::<StatEvent>().is_err());
+
+    let raw = StatEvent::named("r01c2");
+    assert_eq!((raw.type_, raw.config), (perf_type_id_PERF_TYPE_RAW, 0x1c2));
+    assert!("rxyz".parse::<StatEvent>().is_err());
+
+    // A fake PMU with x86 style formats.
+    let root = std::env::temp_dir().join(format!("ruperf-pmus-{}", std::process::id()));
+    let cpu = root.join("cpu");
+    fs::create_dir_all(cpu.join("format")).unwrap();
+    fs::create_dir_all(cpu.join("events")).unwrap();
+    fs::write(cpu.join("type"), "4\n").unwrap();
+    fs::write(cpu.join("format/event"), "config:0-7,32-35\n").unwrap();
+    fs::write(cpu.join("format/umask"), "config:8-15\n").unwrap();
+    fs::write(cpu.join("format/edge"), "config:18\n").unwrap();
+    fs::write(cpu.join("format/ldlat"), "config1:0-15\n").unwrap();
+    fs::write(
+        cpu.join("events/mem-loads"),
+        "event=0xcd,umask=0x1,ldlat=3\n",
+    )
+    .unwrap();
+
+    let e = parse_event("cpu/event=0x13c,umask=0x2,edge/", &root).unwrap();
+    assert_eq!(e.type_, 4);
+    assert_eq!(e.config, 0x1_0004_023c);
+    let e = parse_event("cpu/mem-loads/", &root).unwrap();
+    assert_eq!((e.config, e.config1), (0x1cd, 3));
+    let e = parse_event("cpu/config=0x1234,config2=5/", &root).unwrap();
+    assert_eq!((e.config, e.config2), (0x1234, 5));
+    assert!(parse_event("cpu/bogus=1/", &root).is_err());
+    assert!(parse_event("nopmu/event=1/", &root).is_err());
+    assert!(parse_event("cpu/event=1", &root).is_err());
+    let e = parse_event("cpu/event=0x3c/:u", &root).unwrap();
+    assert_eq!((e.config, e.modifiers.user), (0x3c, true));
+    assert!(!e.uncore);
+    assert!(!e.effective_modifiers().kernel);
+    // perf's spelling, without the `:`.
+    let e = parse_event("cpu/event=0x3c/u", &root).unwrap();
+    assert_eq!((e.config, e.modifiers.user), (0x3c, true));
+    assert!(!e.effective_modifiers().kernel);
+    let e = parse_event("cpu/mem-loads/kpp", &root).unwrap();
+    assert!(e.modifiers.kernel && !e.modifiers.user);
+    assert_eq!(e.modifiers.precise, 2);
+    assert!(matches!(
+        parse_event("cpu/event=0x3c/x", &root),
+        Err(ParseError::InvalidModifiers(..))
+    ));
+
+    // Uncore PMUs are opened on a few CPUs and count every level.
+    let power = root.join("power");
+    fs::create_dir_all(&power).unwrap();
+    fs::write(power.join("type"), "12\n").unwrap();
+    fs::write(power.join("cpumask"), "0\n").unwrap();
+    assert!(is_uncore(&power, &[0, 1, 2, 3]));
+    assert!(is_uncore(&power, &[0]));
+    assert!(!is_uncore(&cpu, &[0]));
+    fs::write(power.join("cpumask"), "0-3\n").unwrap();
+    assert!(!is_uncore(&power, &[0, 1, 2, 3]));
+    let mut e = parse_event("power/config=2/", &root).unwrap();
+    e.uncore = true;
+    let m = e.effective_modifiers();
+    assert!(m.user && m.kernel && m.hv);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn invalid_modifiers_test() {
+    // Suffixes of known events are modifiers, even unknown ones.
+    for bad in &[
+        "cycles:x",
+        "instructions:uq",
+        "L1-dcache-loads:kz",
+        "r01c2:y",
+    ] {
+        assert!(
+            matches!(
+                bad.parse::<StatEvent>(),
+                Err(ParseError::InvalidModifiers(..))
+            ),
+            "{}",
+            bad
+        );
+    }
+    // Anything else is still a tracepoint.
+    assert!(matches!(
+        "nosuch:tracepoint".parse::<StatEvent>(),
+        Err(ParseError::UnknownTracepoint(..))
+    ));
+}
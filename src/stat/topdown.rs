@@ -0,0 +1,296 @@
+//! Top-down analysis: where the CPU's issue slots went.
+//!
+//! Level 1 splits the slots into retiring, bad speculation,
+//! frontend bound and backend bound. Intel cores since Ice Lake
+//! count these in the `perf_metrics` register, read through the
+//! `slots` and `topdown-*` events, and newer ones split each in
+//! two for level 2. Older cores only get level 1, worked out
+//! with the generic formula over their slot and bubble events.
+
+use super::metrics::{Expr, MetricDef};
+use super::spec::{parse_event, StatEvent};
+use crate::utils::ParseError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Titles of the tables the breakdowns are shown in.
+pub const LEVEL1: &str = "top-down level 1";
+pub const LEVEL2: &str = "top-down level 2";
+
+/// Core PMUs that may have the events, hybrid ones last.
+const CORE_PMUS: &[&str] = &["cpu", "cpu_core"];
+
+/// `perf_metrics` events, led by the slots they divide up.
+const PERF_METRICS: &[&str] = &[
+    "slots",
+    "topdown-retiring",
+    "topdown-bad-spec",
+    "topdown-fe-bound",
+    "topdown-be-bound",
+];
+
+/// Level 2 `perf_metrics` events, each a part of one above.
+const PERF_METRICS_L2: &[&str] = &[
+    "topdown-heavy-ops",
+    "topdown-br-mispredict",
+    "topdown-fetch-lat",
+    "topdown-mem-bound",
+];
+
+/// Events of the generic formula, led by the total slots.
+const GENERIC: &[&str] = &[
+    "topdown-total-slots",
+    "topdown-slots-issued",
+    "topdown-slots-retired",
+    "topdown-fetch-bubbles",
+    "topdown-recovery-bubbles",
+];
+
+/// How the breakdown is worked out on this machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    PerfMetrics { level2: bool },
+    Generic,
+}
+
+/// The top-down events of a core PMU.
+#[derive(Debug, Clone)]
+pub struct TopDown {
+    pub pmu: String,
+    pub method: Method,
+    root: PathBuf,
+}
+
+/// Find a core PMU under `root` with top-down events.
+pub fn detect(root: &Path) -> Option<TopDown> {
+    let has_all = |pmu: &str, names: &[&str]| {
+        let events = root.join(pmu).join("events");
+        names.iter().all(|name| events.join(name).is_file())
+    };
+    CORE_PMUS.iter().find_map(|&pmu| {
+        let method = if has_all(pmu, PERF_METRICS) {
+            Method::PerfMetrics {
+                level2: has_all(pmu, PERF_METRICS_L2),
+            }
+        } else if has_all(pmu, GENERIC) {
+            Method::Generic
+        } else {
+            return None;
+        };
+        Some(TopDown {
+            pmu: pmu.to_string(),
+            method,
+            root: root.to_path_buf(),
+        })
+    })
+}
+
+fn div(a: Expr, b: Expr) -> Expr {
+    Expr::Binary(Box::new(a), '/', Box::new(b))
+}
+
+fn sub(a: Expr, b: Expr) -> Expr {
+    Expr::Binary(Box::new(a), '-', Box::new(b))
+}
+
+fn add(a: Expr, b: Expr) -> Expr {
+    Expr::Binary(Box::new(a), '+', Box::new(b))
+}
+
+impl TopDown {
+    /// True if level 2 can be shown.
+    pub fn has_level2(&self) -> bool {
+        self.method == Method::PerfMetrics { level2: true }
+    }
+
+    /// The event `pmu/name/`. Fails if its alias,
+    /// found by `detect()`, can't be parsed after all.
+    fn event(&self, name: &str) -> Result<StatEvent, ParseError> {
+        parse_event(&format!("{}/{}/", self.pmu, name), &self.root)
+    }
+
+    /// The count of `name`, times the scale its alias gives,
+    /// e.g. 4 slots for every cycle `topdown-total-slots` counts.
+    fn scaled(&self, name: &str) -> Result<Expr, ParseError> {
+        let scale = fs::read_to_string(
+            self.root
+                .join(&self.pmu)
+                .join("events")
+                .join(format!("{}.scale", name)),
+        )
+        .ok()
+        .and_then(|s| s.trim().parse::<f64>().ok())
+        .unwrap_or(1.0);
+        let event = Expr::Event(self.event(name)?);
+        if scale == 1.0 {
+            Ok(event)
+        } else {
+            Ok(Expr::Binary(
+                Box::new(event),
+                '*',
+                Box::new(Expr::Number(scale)),
+            ))
+        }
+    }
+
+    /// The events to open for `level`, as one
+    /// group led by the slots the others divide.
+    pub fn events(&self, level: u8) -> Result<Vec<StatEvent>, ParseError> {
+        let names = match self.method {
+            Method::PerfMetrics { .. } if level >= 2 && self.has_level2() => {
+                [PERF_METRICS, PERF_METRICS_L2].concat()
+            }
+            Method::PerfMetrics { .. } => PERF_METRICS.to_vec(),
+            Method::Generic => GENERIC.to_vec(),
+        };
+        names.iter().map(|name| self.event(name)).collect()
+    }
+
+    /// Metrics for the breakdown down to `level`, each a
+    /// fraction of all slots, shown in the top-down tables.
+    pub fn metrics(&self, level: u8) -> Result<Vec<MetricDef>, ParseError> {
+        let def = |name: &str, table, expr| MetricDef {
+            name: format!("topdown-{}", name.replace(' ', "-")),
+            label: name.to_string(),
+            expr,
+            table: Some(table),
+        };
+        let e = |name: &str| self.scaled(name);
+        let mut defs = Vec::new();
+        match self.method {
+            Method::PerfMetrics { .. } => {
+                // The four add up to the slots, but are read a
+                // little apart, so divide by their sum instead.
+                let total = || -> Result<Expr, ParseError> {
+                    let l1 = add(e("topdown-retiring")?, e("topdown-bad-spec")?);
+                    Ok(add(l1, add(e("topdown-fe-bound")?, e("topdown-be-bound")?)))
+                };
+                let level1 = [
+                    ("retiring", "topdown-retiring"),
+                    ("bad speculation", "topdown-bad-spec"),
+                    ("frontend bound", "topdown-fe-bound"),
+                    ("backend bound", "topdown-be-bound"),
+                ];
+                for (name, event) in level1.iter() {
+                    defs.push(def(name, LEVEL1, div(e(event)?, total()?)));
+                }
+                if level >= 2 && self.has_level2() {
+                    // Each level 1 share is split into the part
+                    // an event counts and the rest of it.
+                    let level2 = [
+                        ("heavy operations", "light operations"),
+                        ("branch mispredicts", "machine clears"),
+                        ("fetch latency", "fetch bandwidth"),
+                        ("memory bound", "core bound"),
+                    ];
+                    for (((part, rest), (_, whole)), event) in
+                        level2.iter().zip(&level1).zip(PERF_METRICS_L2)
+                    {
+                        defs.push(def(part, LEVEL2, div(e(event)?, total()?)));
+                        let remainder = sub(e(whole)?, e(event)?);
+                        defs.push(def(rest, LEVEL2, div(remainder, total()?)));
+                    }
+                }
+            }
+            Method::Generic => {
+                let total = || e("topdown-total-slots");
+                let bad = add(
+                    sub(e("topdown-slots-issued")?, e("topdown-slots-retired")?),
+                    e("topdown-recovery-bubbles")?,
+                );
+                let used = add(
+                    e("topdown-fetch-bubbles")?,
+                    add(e("topdown-slots-issued")?, e("topdown-recovery-bubbles")?),
+                );
+                let backend = sub(total()?, used);
+                defs.push(def(
+                    "retiring",
+                    LEVEL1,
+                    div(e("topdown-slots-retired")?, total()?),
+                ));
+                defs.push(def("bad speculation", LEVEL1, div(bad, total()?)));
+                defs.push(def(
+                    "frontend bound",
+                    LEVEL1,
+                    div(e("topdown-fetch-bubbles")?, total()?),
+                ));
+                defs.push(def("backend bound", LEVEL1, div(backend, total()?)));
+            }
+        }
+        Ok(defs)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn topdown_test() {
+    use super::metrics::compute;
+
+    let root = std::env::temp_dir().join(format!("ruperf-topdown-{}", std::process::id()));
+    let cpu = root.join("cpu");
+    fs::create_dir_all(cpu.join("events")).unwrap();
+    fs::create_dir_all(cpu.join("format")).unwrap();
+    fs::write(cpu.join("type"), "4\n").unwrap();
+    fs::write(cpu.join("format/event"), "config:0-7\n").unwrap();
+    fs::write(cpu.join("format/umask"), "config:8-15\n").unwrap();
+    assert!(detect(&root).is_none());
+
+    let generic = [
+        ("topdown-total-slots", "event=0x3c,umask=0x0", Some("4")),
+        ("topdown-slots-issued", "event=0xe,umask=0x1", None),
+        ("topdown-slots-retired", "event=0xc2,umask=0x2", None),
+        ("topdown-fetch-bubbles", "event=0x9c,umask=0x1", None),
+        ("topdown-recovery-bubbles", "event=0xd,umask=0x3", Some("4")),
+    ];
+    for (name, terms, scale) in generic.iter() {
+        fs::write(cpu.join("events").join(name), terms).unwrap();
+        if let Some(scale) = scale {
+            fs::write(cpu.join("events").join(format!("{}.scale", name)), scale).unwrap();
+        }
+    }
+    let td = detect(&root).unwrap();
+    assert_eq!(td.method, Method::Generic);
+    assert!(!td.has_level2());
+    let events = td.events(2).unwrap();
+    assert_eq!(events.len(), 5);
+    assert_eq!(events[0].config, 0x3c);
+
+    // 100 cycles of 4 slots: 120 issued, 100 retired,
+    // 10 recovery cycles and 60 fetch bubbles.
+    let values = [
+        Some(100.0),
+        Some(120.0),
+        Some(100.0),
+        Some(60.0),
+        Some(10.0),
+    ];
+    let event_refs: Vec<&StatEvent> = events.iter().collect();
+    let shares: Vec<(String, f64)> = compute(&td.metrics(1).unwrap(), &event_refs, &values, 1.0)
+        .into_iter()
+        .map(|(_, m)| (m.name, m.value))
+        .collect();
+    assert_eq!(
+        shares,
+        vec![
+            ("retiring".to_string(), 0.25),
+            ("bad speculation".to_string(), 0.15),
+            ("frontend bound".to_string(), 0.15),
+            ("backend bound".to_string(), 0.45),
+        ]
+    );
+
+    for name in PERF_METRICS.iter().chain(PERF_METRICS_L2) {
+        fs::write(cpu.join("events").join(name), "event=0x0,umask=0x80").unwrap();
+    }
+    let td = detect(&root).unwrap();
+    assert_eq!(td.method, Method::PerfMetrics { level2: true });
+    assert_eq!(td.events(1).unwrap().len(), 5);
+    assert_eq!(td.events(2).unwrap().len(), 9);
+    assert_eq!(td.metrics(2).unwrap().len(), 12);
+
+    // Aliases that stop parsing make top-down unavailable.
+    fs::remove_file(cpu.join("format/umask")).unwrap();
+    assert!(td.events(1).is_err());
+    assert!(td.metrics(1).is_err());
+    fs::remove_dir_all(&root).unwrap();
+}
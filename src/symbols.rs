@@ -0,0 +1,198 @@
+//! Symbol resolution for sampled instruction pointers.
+//!
+//! A `Symbolizer` tracks the executable mappings of each
+//! profiled process, as reported by `mmap` records or
+//! `/proc/<pid>/maps` snapshots, and resolves addresses
+//! to a shared object and the function inside it.
+
+pub mod elf;
+pub mod maps;
+
+use elf::{demangle, SymbolTable};
+use maps::MapEntry;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Pseudo shared object kernel samples are attributed to.
+pub const KERNEL_DSO: &str = "[kernel.kallsyms]";
+
+/// Where an address was resolved to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    /// Path of the shared object, or a pseudo
+    /// path like `[vdso]` or `[unknown]`.
+    pub dso: String,
+    /// Demangled function name, if one was found.
+    pub symbol: Option<String>,
+    /// Address the function is linked at in `dso`.
+    pub symbol_start: u64,
+    /// Address linked at in `dso`, the
+    /// raw address if no mapping matched.
+    pub addr: u64,
+    pub kernel: bool,
+}
+
+impl Location {
+    /// Short name of the shared object, e.g. `libc.so.6`.
+    pub fn dso_name(&self) -> &str {
+        Path::new(&self.dso)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(&self.dso)
+    }
+
+    /// Function name, or the raw address
+    /// for code without symbols.
+    pub fn symbol_name(&self) -> String {
+        match &self.symbol {
+            Some(s) => s.clone(),
+            None => format!("{:#x}", self.addr),
+        }
+    }
+}
+
+/// Resolves addresses of profiled processes.
+#[derive(Default)]
+pub struct Symbolizer {
+    /// Mappings announced by the kernel, per pid, oldest first.
+    maps: HashMap<u32, Vec<MapEntry>>,
+    /// Latest `/proc/<pid>/maps` snapshot, per pid.
+    snapshots: HashMap<u32, Vec<MapEntry>>,
+    /// Parent of each forked process.
+    parents: HashMap<u32, u32>,
+    /// Symbol tables by path, `None` if unreadable.
+    tables: HashMap<String, Option<SymbolTable>>,
+    kernel: Option<SymbolTable>,
+}
+
+impl Symbolizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `map` was mapped into `pid`.
+    pub fn add_map(&mut self, pid: u32, map: MapEntry) {
+        self.maps.entry(pid).or_default().push(map);
+    }
+
+    /// Replace the maps snapshot of `pid`.
+    pub fn add_snapshot(&mut self, pid: u32, maps: Vec<MapEntry>) {
+        self.snapshots.insert(pid, maps);
+    }
+
+    /// True if any mapping of `pid` is known.
+    pub fn has_maps(&self, pid: u32) -> bool {
+        self.maps.contains_key(&pid) || self.snapshots.contains_key(&pid)
+    }
+
+    /// Drop everything known about `pid`'s
+    /// mappings, e.g. after it exec'd.
+    pub fn forget(&mut self, pid: u32) {
+        self.maps.remove(&pid);
+        self.snapshots.remove(&pid);
+        self.parents.remove(&pid);
+    }
+
+    /// Record that `pid` was forked from `ppid`, and
+    /// so shares its mappings until it execs.
+    pub fn add_fork(&mut self, pid: u32, ppid: u32) {
+        if pid != ppid {
+            self.parents.insert(pid, ppid);
+        }
+    }
+
+    /// Find the mapping of `pid` containing `ip`, newest
+    /// mappings first, then snapshots, then the parents.
+    pub fn find_map(&self, pid: u32, ip: u64) -> Option<&MapEntry> {
+        let mut pid = pid;
+        // Bounded walk in case of a fork cycle from pid reuse.
+        for _ in 0..64 {
+            let found = self
+                .maps
+                .get(&pid)
+                .and_then(|m| m.iter().rev().find(|m| m.contains(ip)))
+                .or_else(|| {
+                    self.snapshots
+                        .get(&pid)
+                        .and_then(|m| m.iter().find(|m| m.contains(ip)))
+                });
+            if found.is_some() {
+                return found;
+            }
+            pid = *self.parents.get(&pid)?;
+        }
+        None
+    }
+
+    /// Symbol table for the file at `path`, loaded on first use.
+    pub fn table(&mut self, path: &str) -> Option<&SymbolTable> {
+        self.tables
+            .entry(path.to_string())
+            .or_insert_with(|| {
+                if path.starts_with('[') {
+                    None
+                } else {
+                    SymbolTable::load(Path::new(path))
+                }
+            })
+            .as_ref()
+    }
+
+    /// Resolve `ip` sampled in `pid`.
+    pub fn resolve(&mut self, pid: u32, ip: u64, kernel: bool) -> Location {
+        if kernel {
+            let table = self.kernel.get_or_insert_with(SymbolTable::kallsyms);
+            let sym = table.lookup(ip);
+            return Location {
+                dso: KERNEL_DSO.to_string(),
+                symbol: sym.map(|s| s.name.clone()),
+                symbol_start: sym.map(|s| s.start).unwrap_or(0),
+                addr: ip,
+                kernel: true,
+            };
+        }
+        let map = match self.find_map(pid, ip) {
+            Some(m) => m.clone(),
+            None => {
+                return Location {
+                    dso: "[unknown]".to_string(),
+                    symbol: None,
+                    symbol_start: 0,
+                    addr: ip,
+                    kernel: false,
+                }
+            }
+        };
+        let offset = ip - map.start + map.pgoff;
+        let (addr, sym) = match self.table(&map.path) {
+            Some(table) => {
+                let addr = table.file_offset_to_vaddr(offset);
+                (
+                    addr,
+                    table.lookup(addr).map(|s| (demangle(&s.name), s.start)),
+                )
+            }
+            None => (offset, None),
+        };
+        Location {
+            dso: map.path,
+            symbol: sym.as_ref().map(|(name, _)| name.clone()),
+            symbol_start: sym.map(|(_, start)| start).unwrap_or(0),
+            addr,
+            kernel: false,
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn resolve_self_test() {
+    let mut symbolizer = Symbolizer::new();
+    let pid = std::process::id();
+    symbolizer.add_snapshot(pid, maps::read_maps(pid as i32).unwrap());
+    let ip = resolve_self_test as usize as u64;
+    let loc = symbolizer.resolve(pid, ip, false);
+    assert_eq!(loc.dso, std::env::current_exe().unwrap().to_str().unwrap());
+    assert!(loc.symbol_name().ends_with("resolve_self_test"));
+    assert_eq!(symbolizer.resolve(pid, 8, false).dso, "[unknown]");
+}
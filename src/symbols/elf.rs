@@ -0,0 +1,222 @@
+//! Function symbol tables loaded from ELF files.
+//!
+//! `.symtab` is preferred, falling back to `.dynsym`
+//! for stripped binaries. A stripped binary with a
+//! separate debug file installed under `/usr/lib/debug`
+//! (by build-id or path) is resolved through that file.
+
+use object::{Object, ObjectSegment, ObjectSymbol, SymbolKind};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where separate debug info files are installed.
+const DEBUG_DIR: &str = "/usr/lib/debug";
+
+/// A function symbol.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    /// Virtual address the symbol is linked at.
+    pub start: u64,
+    pub size: u64,
+}
+
+/// Function symbols of one ELF file, along
+/// with the `PT_LOAD` segments needed to map
+/// file offsets to linked addresses.
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    /// Sorted by `start`.
+    symbols: Vec<Symbol>,
+    /// `(file offset, file size, vaddr)` of each loadable segment.
+    segments: Vec<(u64, u64, u64)>,
+    /// True if only `.dynsym` was available.
+    pub stripped: bool,
+}
+
+impl SymbolTable {
+    /// Load the symbols of the ELF file at `path`.
+    /// Returns `None` if it can't be read or parsed.
+    pub fn load(path: &Path) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        let file = object::File::parse(&*data).ok()?;
+        let segments = file
+            .segments()
+            .map(|s| {
+                let (offset, size) = s.file_range();
+                (offset, size, s.address())
+            })
+            .collect();
+        let mut table = Self {
+            symbols: functions(file.symbols()),
+            segments,
+            stripped: false,
+        };
+        if table.symbols.is_empty() {
+            // Segments come from the binary that is actually
+            // mapped, only the symbols are borrowed from its
+            // separate debug file.
+            if let Some(symbols) = debug_file(&file, path)
+                .and_then(|p| fs::read(p).ok())
+                .and_then(|d| {
+                    object::File::parse(&*d)
+                        .ok()
+                        .map(|f| functions(f.symbols()))
+                })
+            {
+                table.symbols = symbols;
+            }
+        }
+        if table.symbols.is_empty() {
+            table.symbols = functions(file.dynamic_symbols());
+            table.stripped = true;
+        }
+        Some(table)
+    }
+
+    /// Build a table from `/proc/kallsyms`. Addresses
+    /// are only visible with sufficient privileges, when
+    /// they are hidden an empty table is returned.
+    pub fn kallsyms() -> Self {
+        let text = fs::read_to_string("/proc/kallsyms").unwrap_or_default();
+        let mut symbols: Vec<Symbol> = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let addr = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let kind = fields.next()?;
+                let name = fields.next()?;
+                if addr == 0 || !matches!(kind, "t" | "T" | "w" | "W") {
+                    return None;
+                }
+                Some(Symbol {
+                    name: name.to_string(),
+                    start: addr,
+                    size: 0,
+                })
+            })
+            .collect();
+        symbols.sort_by_key(|s| s.start);
+        Self {
+            symbols,
+            segments: Vec::new(),
+            stripped: false,
+        }
+    }
+
+    /// Translate an offset into the file to
+    /// the address it is linked at. Files without
+    /// segments (kallsyms) are identity mapped.
+    pub fn file_offset_to_vaddr(&self, offset: u64) -> u64 {
+        self.segments
+            .iter()
+            .find(|(start, size, _)| *start <= offset && offset < start + size)
+            .map(|(start, _, vaddr)| offset - start + vaddr)
+            .unwrap_or(offset)
+    }
+
+    /// Find the function containing `vaddr`. Symbols
+    /// without a size cover everything up to the next one.
+    pub fn lookup(&self, vaddr: u64) -> Option<&Symbol> {
+        let i = match self.symbols.binary_search_by_key(&vaddr, |s| s.start) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let sym = &self.symbols[i];
+        if sym.size == 0 || vaddr < sym.start + sym.size {
+            Some(sym)
+        } else {
+            None
+        }
+    }
+
+    /// Address just past the end of the function
+    /// containing `vaddr`, the next symbol's start
+    /// for symbols without a size.
+    pub fn end_of(&self, vaddr: u64) -> Option<u64> {
+        let sym = self.lookup(vaddr)?;
+        if sym.size != 0 {
+            return Some(sym.start + sym.size);
+        }
+        self.symbols
+            .iter()
+            .find(|s| s.start > sym.start)
+            .map(|s| s.start)
+    }
+}
+
+/// Collect the sized function symbols from `symbols`,
+/// sorted by address with aliases removed.
+fn functions<'data, I, S>(symbols: I) -> Vec<Symbol>
+where
+    I: Iterator<Item = S>,
+    S: ObjectSymbol<'data>,
+{
+    let mut out: Vec<Symbol> = symbols
+        .filter(|s| s.kind() == SymbolKind::Text && s.is_definition() && s.address() != 0)
+        .filter_map(|s| {
+            Some(Symbol {
+                name: s.name().ok()?.to_string(),
+                start: s.address(),
+                size: s.size(),
+            })
+        })
+        .collect();
+    out.sort_by_key(|s| s.start);
+    out.dedup_by_key(|s| s.start);
+    out
+}
+
+/// Locate the separate debug file for a stripped
+/// binary, first by build-id and then by the
+/// `.gnu_debuglink` name next to the original path.
+fn debug_file(file: &object::File, path: &Path) -> Option<PathBuf> {
+    if let Ok(Some(id)) = file.build_id() {
+        if id.len() > 1 {
+            let hex: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+            let candidate = Path::new(DEBUG_DIR)
+                .join(".build-id")
+                .join(&hex[..2])
+                .join(format!("{}.debug", &hex[2..]));
+            if candidate.exists() {
+                return Some(candidate);
+            }
+        }
+    }
+    if let Ok(Some((name, _crc))) = file.gnu_debuglink() {
+        let name = String::from_utf8_lossy(name).into_owned();
+        let dir = path.parent()?;
+        let candidates = [
+            dir.join(&name),
+            dir.join(".debug").join(&name),
+            Path::new(DEBUG_DIR)
+                .join(dir.strip_prefix("/").unwrap_or(dir))
+                .join(&name),
+        ];
+        return candidates.iter().find(|p| p.exists()).cloned();
+    }
+    None
+}
+
+/// Demangle Rust symbol names, dropping the trailing
+/// hash. Other names are returned unchanged.
+pub fn demangle(name: &str) -> String {
+    format!("{:#}", rustc_demangle::demangle(name))
+}
+
+#[cfg(test)]
+#[test]
+fn load_self_test() {
+    let table = SymbolTable::load(&std::env::current_exe().unwrap()).unwrap();
+    let addr = table
+        .symbols
+        .iter()
+        .find(|s| demangle(&s.name).ends_with("load_self_test"))
+        .unwrap()
+        .start;
+    let sym = table.lookup(addr + 1).unwrap();
+    assert!(demangle(&sym.name).ends_with("load_self_test"));
+    assert!(table.end_of(addr).unwrap() > addr);
+    assert!(table.lookup(0).is_none());
+}
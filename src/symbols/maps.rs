@@ -0,0 +1,82 @@
+//! Parsing of `/proc/<pid>/maps`, which `ruperf record`
+//! snapshots so samples can still be attributed to a
+//! mapping when the kernel's `mmap` records are missing.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// An executable mapping in a process' address space.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MapEntry {
+    pub start: u64,
+    pub end: u64,
+    /// Offset into the mapped file of `start`.
+    pub pgoff: u64,
+    /// Backing file, or a pseudo path like `[vdso]`.
+    pub path: String,
+}
+
+impl MapEntry {
+    /// True if `ip` falls inside this mapping.
+    pub fn contains(&self, ip: u64) -> bool {
+        self.start <= ip && ip < self.end
+    }
+}
+
+/// Parse the text of a `maps` file,
+/// keeping only executable mappings.
+pub fn parse_maps(text: &str) -> Vec<MapEntry> {
+    text.lines().filter_map(parse_line).collect()
+}
+
+/// `start-end perms offset dev inode [path]`
+fn parse_line(line: &str) -> Option<MapEntry> {
+    let mut fields = line.split_whitespace();
+    let (start, end) = fields.next()?.split_once('-')?;
+    let perms = fields.next()?;
+    let pgoff = fields.next()?;
+    let _dev = fields.next()?;
+    let _inode = fields.next()?;
+    if !perms.contains('x') {
+        return None;
+    }
+    // Paths may contain spaces, so rejoin whatever is left.
+    let path = fields.collect::<Vec<_>>().join(" ");
+    Some(MapEntry {
+        start: u64::from_str_radix(start, 16).ok()?,
+        end: u64::from_str_radix(end, 16).ok()?,
+        pgoff: u64::from_str_radix(pgoff, 16).ok()?,
+        path: if path.is_empty() {
+            "[anon]".to_string()
+        } else {
+            path
+        },
+    })
+}
+
+/// Snapshot the executable mappings of `pid`.
+/// Returns `None` once the process is gone.
+pub fn read_maps(pid: i32) -> Option<Vec<MapEntry>> {
+    let text = fs::read_to_string(format!("/proc/{}/maps", pid)).ok()?;
+    Some(parse_maps(&text))
+}
+
+#[cfg(test)]
+#[test]
+fn parse_maps_test() {
+    let text = "\
+55d0c0a00000-55d0c0a04000 r--p 00000000 08:01 1234 /usr/bin/dash
+55d0c0a04000-55d0c0a17000 r-xp 00004000 08:01 1234 /usr/bin/dash
+7ffd5a5f1000-7ffd5a5f3000 r-xp 00000000 00:00 0 [vdso]
+7f0000000000-7f0000001000 r-xp 00000000 00:00 0
+";
+    let maps = parse_maps(text);
+    assert_eq!(maps.len(), 3);
+    assert_eq!(maps[0].start, 0x55d0c0a04000);
+    assert_eq!(maps[0].pgoff, 0x4000);
+    assert_eq!(maps[0].path, "/usr/bin/dash");
+    assert!(maps[0].contains(0x55d0c0a04010));
+    assert_eq!(maps[1].path, "[vdso]");
+    assert_eq!(maps[2].path, "[anon]");
+    assert!(!read_maps(std::process::id() as i32).unwrap().is_empty());
+}
@@ -41,7 +41,16 @@ pub fn test_counts() -> Test {
         );
         let start: isize;
         let stop: isize;
-        let event = Event::new(event_to_run, Some(pid_child));
+        let event = match Event::new(event_to_run, Some(pid_child)) {
+            Ok(event) => event,
+            Err(e) => {
+                unsafe {
+                    libc::kill(pid_child, libc::SIGKILL);
+                    libc::waitpid(pid_child, std::ptr::null_mut(), 0);
+                }
+                return fail(format!("\nINFO:\t{}", e), settings);
+            }
+        };
         let mut buf = [0];
         let nread = parent_reader.read(&mut buf).unwrap();
         if nread != 1 {
@@ -87,7 +96,7 @@ pub fn test_counts() -> Test {
 
     fn test_cycles() -> Test {
         fn cycles(settings: &RunSettings) -> TestResult {
-            event_counter(StatEvent::Cycles, 1000, settings)
+            event_counter(StatEvent::named("cycles"), 1000, settings)
         }
         Test {
             name: "cycles_test".to_string(),
@@ -99,7 +108,7 @@ pub fn test_counts() -> Test {
     }
     fn test_instructions() -> Test {
         fn instructions(settings: &RunSettings) -> TestResult {
-            event_counter(StatEvent::Instructions, 1000, settings)
+            event_counter(StatEvent::named("instructions"), 1000, settings)
         }
         Test {
             name: "instructions_test".to_string(),
@@ -111,7 +120,7 @@ pub fn test_counts() -> Test {
     }
     fn test_context_switches() -> Test {
         fn context_switches(settings: &RunSettings) -> TestResult {
-            event_counter(StatEvent::ContextSwitches, 0, settings)
+            event_counter(StatEvent::named("context-switches"), 0, settings)
         }
         Test {
             name: "context_switch_test".to_string(),
@@ -123,7 +132,7 @@ pub fn test_counts() -> Test {
     }
     fn test_l1d_cache_read() -> Test {
         fn l1d_cache_read(settings: &RunSettings) -> TestResult {
-            event_counter(StatEvent::L1DCacheRead, 1000, settings)
+            event_counter(StatEvent::named("L1D-cache-reads"), 1000, settings)
         }
         Test {
             name: "L1D_cache_read_test".to_string(),
@@ -135,7 +144,7 @@ pub fn test_counts() -> Test {
     }
     fn test_l1d_cache_write() -> Test {
         fn l1d_cache_write(settings: &RunSettings) -> TestResult {
-            event_counter(StatEvent::L1DCacheWrite, 0, settings)
+            event_counter(StatEvent::named("L1D-cache-writes"), 0, settings)
         }
         Test {
             name: "L1D_cache_write_test".to_string(),
@@ -147,7 +156,7 @@ pub fn test_counts() -> Test {
     }
     fn test_l1d_cache_read_misses() -> Test {
         fn l1d_cache_read_misses(settings: &RunSettings) -> TestResult {
-            event_counter(StatEvent::L1DCacheReadMiss, 0, settings)
+            event_counter(StatEvent::named("L1D-cache-read-misses"), 0, settings)
         }
         Test {
             name: "L1D_cache_read_miss_test".to_string(),
@@ -159,7 +168,7 @@ pub fn test_counts() -> Test {
     }
     fn test_l1i_cache_read_misses() -> Test {
         fn l1i_cache_read_misses(settings: &RunSettings) -> TestResult {
-            event_counter(StatEvent::L1ICacheReadMiss, 0, settings)
+            event_counter(StatEvent::named("L1I-cache-read-misses"), 0, settings)
         }
         Test {
             name: "L1I_cache_read_miss_test".to_string(),
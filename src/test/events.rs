@@ -34,7 +34,10 @@ fn event_sanity_check(event: StatEvent, settings: &RunSettings) -> TestResult {
         TestResult::Failed("(1)".to_string())
     }
 
-    let event = Event::new(event, None);
+    let event = match Event::new(event, None) {
+        Ok(event) => event,
+        Err(e) => return fail(format!("\nINFO:\t{}", e), settings),
+    };
     let begin_count: isize = event.start_counter().unwrap();
     useless_stuff();
     let end_count = event.stop_counter().unwrap();
@@ -65,10 +68,10 @@ fn dummy(_settings: &RunSettings) -> TestResult {
 
 // This is the parent test for all the event subtests.
 pub fn test_events() -> Test {
-    // This tests StatEvent::Cycles for proper functionality
+    // This tests the cycles event for proper functionality
     fn test_cycles_open() -> Test {
         fn cycles_open(settings: &RunSettings) -> TestResult {
-            event_sanity_check(StatEvent::Cycles, settings)
+            event_sanity_check(StatEvent::named("cycles"), settings)
         }
         Test {
             name: "cycles_open".to_string(),
@@ -79,10 +82,10 @@ pub fn test_events() -> Test {
         }
     }
 
-    // This tests StatEvent::Instructions for proper functionality
+    // This tests the instructions event for proper functionality
     fn test_instructions_open() -> Test {
         fn instructions_open(settings: &RunSettings) -> TestResult {
-            event_sanity_check(StatEvent::Instructions, settings)
+            event_sanity_check(StatEvent::named("instructions"), settings)
         }
         Test {
             name: "instructions_open".to_string(),
@@ -0,0 +1,511 @@
+//! # Top driver.
+//! <p> Usage: <em> ruperf top [OPTION] </em>
+//! Samples every CPU, or a single process with `--pid`, and
+//! redraws the hottest symbols in the terminal until `q` is pressed. </p>
+
+extern crate structopt;
+use crate::bindings::*;
+use crate::event::cpu::online_cpus;
+use crate::event::open::*;
+use crate::event::ring::{self, RingBuffer};
+use crate::event::sample::*;
+use crate::event::task::{read_comm, threads_of};
+use crate::stat::StatEvent;
+use crate::symbols::maps::{read_maps, MapEntry};
+use crate::symbols::Symbolizer;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use structopt::StructOpt;
+
+/// Share of the previous weight an entry keeps at each refresh.
+const DECAY: f64 = 7.0 / 8.0;
+
+/// Entries decayed below this share of the total are dropped.
+const MIN_SHARE: f64 = 0.0001;
+
+/// How long to block on the ring buffers
+/// before checking for a key press.
+const POLL_TIMEOUT_MS: i32 = 100;
+
+/// Lines used by the header and the key help.
+const CHROME_LINES: usize = 5;
+
+/// Configuration settings for running top. Samples the whole system unless
+/// `--pid` is given, which needs no more than access to that process. The
+/// whole system is sampled in the kernel too, unless the event's modifiers
+/// say otherwise. See `./ruperf top --help` for more information.
+#[derive(Debug, StructOpt)]
+pub struct TopOptions {
+    #[structopt(short, long, help = "Event to sample", default_value = "cpu-clock")]
+    pub event: StatEvent,
+
+    #[structopt(short, long, help = "Only sample the threads of this process")]
+    pub pid: Option<i32>,
+
+    #[structopt(
+        short = "F",
+        long = "freq",
+        help = "Samples per second",
+        default_value = "1000"
+    )]
+    pub freq: u64,
+
+    #[structopt(short, long, help = "Seconds between refreshes", default_value = "1")]
+    pub delay: u64,
+
+    #[structopt(
+        short = "m",
+        long = "mmap-pages",
+        help = "Ring buffer data pages per event, a power of two",
+        default_value = "16"
+    )]
+    pub mmap_pages: usize,
+}
+
+/// Orders the rows can be displayed in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TopSort {
+    Overhead,
+    Pid,
+    Comm,
+    Dso,
+    Sym,
+}
+
+impl TopSort {
+    /// The order `s` switches to.
+    fn next(self) -> Self {
+        match self {
+            TopSort::Overhead => TopSort::Pid,
+            TopSort::Pid => TopSort::Comm,
+            TopSort::Comm => TopSort::Dso,
+            TopSort::Dso => TopSort::Sym,
+            TopSort::Sym => TopSort::Overhead,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TopSort::Overhead => "overhead",
+            TopSort::Pid => "pid",
+            TopSort::Comm => "command",
+            TopSort::Dso => "shared object",
+            TopSort::Sym => "symbol",
+        }
+    }
+}
+
+/// A histogram row.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct TopKey {
+    pid: u32,
+    comm: String,
+    dso: String,
+    sym: String,
+}
+
+/// Which filter a prompt is editing.
+#[derive(Debug, Copy, Clone)]
+enum Prompt {
+    Pid,
+    Comm,
+}
+
+/// Everything the display depends on.
+struct TopState {
+    histogram: HashMap<TopKey, f64>,
+    /// Samples taken since the last refresh.
+    samples: u64,
+    sort: TopSort,
+    pid_filter: Option<u32>,
+    comm_filter: Option<String>,
+    frozen: bool,
+    prompt: Option<(Prompt, String)>,
+    quit: bool,
+}
+
+impl TopState {
+    fn matches(&self, key: &TopKey) -> bool {
+        self.pid_filter.is_none_or(|p| p == key.pid)
+            && self
+                .comm_filter
+                .as_ref()
+                .is_none_or(|c| key.comm.contains(c.as_str()))
+    }
+
+    /// Age every entry and drop those that faded out.
+    fn decay(&mut self) {
+        let total: f64 = self.histogram.values().sum();
+        for weight in self.histogram.values_mut() {
+            *weight *= DECAY;
+        }
+        self.histogram.retain(|_, w| *w > total * MIN_SHARE);
+    }
+
+    /// Apply one byte of keyboard input.
+    fn key(&mut self, byte: u8) {
+        if let Some((kind, mut text)) = self.prompt.take() {
+            match byte {
+                b'\r' | b'\n' => {
+                    let text = text.trim().to_string();
+                    match kind {
+                        Prompt::Pid => self.pid_filter = text.parse().ok(),
+                        Prompt::Comm if text.is_empty() => self.comm_filter = None,
+                        Prompt::Comm => self.comm_filter = Some(text),
+                    }
+                }
+                // Escape cancels the prompt.
+                27 => {}
+                // Backspace or delete.
+                8 | 127 => {
+                    text.pop();
+                    self.prompt = Some((kind, text));
+                }
+                c if c.is_ascii_graphic() || c == b' ' => {
+                    text.push(c as char);
+                    self.prompt = Some((kind, text));
+                }
+                _ => self.prompt = Some((kind, text)),
+            }
+            return;
+        }
+        match byte {
+            // Ctrl-C, since the terminal is in raw mode.
+            b'q' | 3 => self.quit = true,
+            b'f' => self.frozen = !self.frozen,
+            b's' => self.sort = self.sort.next(),
+            b'z' => self.histogram.clear(),
+            b'p' => self.prompt = Some((Prompt::Pid, String::new())),
+            b'c' => self.prompt = Some((Prompt::Comm, String::new())),
+            _ => {}
+        }
+    }
+}
+
+/// Puts the terminal into unbuffered, no echo mode
+/// for reading single key presses, restoring it when dropped.
+struct RawTerminal {
+    saved: Option<libc::termios>,
+}
+
+impl RawTerminal {
+    fn new() -> Self {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 {
+                return Self { saved: None };
+            }
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) == -1 {
+                return Self { saved: None };
+            }
+            let mut raw = saved;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 0;
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+            Self { saved: Some(saved) }
+        }
+    }
+
+    /// Any bytes typed since the last call.
+    fn read_keys(&self) -> Vec<u8> {
+        if self.saved.is_none() {
+            return Vec::new();
+        }
+        let mut buf = [0_u8; 32];
+        let n = unsafe {
+            libc::read(
+                libc::STDIN_FILENO,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if n <= 0 {
+            return Vec::new();
+        }
+        buf[..n as usize].to_vec()
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.saved {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+            }
+        }
+    }
+}
+
+/// Rows available in the terminal, 24 if unknown.
+fn terminal_rows() -> usize {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if ret == -1 || size.ws_row == 0 {
+        24
+    } else {
+        size.ws_row as usize
+    }
+}
+
+/// Draw the histogram.
+fn draw(state: &TopState, event: &StatEvent, target: &str, elapsed: Duration) -> io::Result<()> {
+    let mut rows: Vec<(&TopKey, f64)> = state
+        .histogram
+        .iter()
+        .filter(|(k, _)| state.matches(k))
+        .map(|(k, w)| (k, *w))
+        .collect();
+    let total: f64 = rows.iter().map(|(_, w)| w).sum();
+    match state.sort {
+        TopSort::Overhead => rows.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap()),
+        TopSort::Pid => rows.sort_by_key(|(k, _)| k.pid),
+        TopSort::Comm => rows.sort_by(|a, b| a.0.comm.cmp(&b.0.comm)),
+        TopSort::Dso => rows.sort_by(|a, b| a.0.dso.cmp(&b.0.dso)),
+        TopSort::Sym => rows.sort_by(|a, b| a.0.sym.cmp(&b.0.sym)),
+    }
+
+    let mut out = String::new();
+    // Home the cursor and clear the screen.
+    out.push_str("\x1b[H\x1b[2J");
+    out.push_str(&format!(
+        "ruperf top - {} - {:.0} samples/s  event: {}  sort: {}",
+        target,
+        state.samples as f64 / elapsed.as_secs_f64().max(0.001),
+        event,
+        state.sort.name()
+    ));
+    if let Some(pid) = state.pid_filter {
+        out.push_str(&format!("  pid: {}", pid));
+    }
+    if let Some(comm) = &state.comm_filter {
+        out.push_str(&format!("  comm: {}", comm));
+    }
+    if state.frozen {
+        out.push_str("  [frozen]");
+    }
+    out.push_str("\n\n");
+    out.push_str(&format!(
+        "{:>9}  {:>7}  {:<16}  {:<24}  {}\n",
+        "Overhead", "Pid", "Command", "Shared Object", "Symbol"
+    ));
+    let space = terminal_rows().saturating_sub(CHROME_LINES);
+    for (key, weight) in rows.iter().take(space) {
+        out.push_str(&format!(
+            "{:>8.2}%  {:>7}  {:<16.16}  {:<24.24}  {}\n",
+            weight * 100.0 / total.max(f64::MIN_POSITIVE),
+            key.pid,
+            key.comm,
+            key.dso,
+            key.sym
+        ));
+    }
+    match &state.prompt {
+        Some((Prompt::Pid, text)) => {
+            out.push_str(&format!("pid to show (empty for all): {}", text))
+        }
+        Some((Prompt::Comm, text)) => {
+            out.push_str(&format!("command to show (empty for all): {}", text))
+        }
+        None => out.push_str("q quit  f freeze  s sort  p pid filter  c command filter  z zero"),
+    }
+    let stdout = io::stdout();
+    let mut lock = stdout.lock();
+    lock.write_all(out.as_bytes())?;
+    lock.flush()
+}
+
+/// Run perf top.
+pub fn run_top(options: TopOptions) {
+    let mut options = options;
+    // Sampling every CPU already takes the privileges kernel
+    // samples need, and much of the time is spent there.
+    let m = &mut options.event.modifiers;
+    if options.pid.is_none() && !m.has_privileges() {
+        m.user = true;
+        m.kernel = true;
+    }
+    let sample_type = perf_event_sample_format_PERF_SAMPLE_IP
+        | perf_event_sample_format_PERF_SAMPLE_TID
+        | perf_event_sample_format_PERF_SAMPLE_PERIOD;
+    let period = SamplePeriod::Frequency(options.freq);
+    // `sample_open()` adds to the sample type, for `:S` among
+    // others, and samples must be parsed with what it chose.
+    let attr = sample_open(&options.event, period, sample_type).unwrap_or_else(|e| {
+        eprintln!("ruperf top: {}", e);
+        std::process::exit(1);
+    });
+
+    // System wide: one event per CPU watching every task.
+    // Per process: one event per thread, on any CPU.
+    let targets: Vec<(i32, i32)> = match options.pid {
+        Some(pid) => threads_of(pid).into_iter().map(|t| (t, -1)).collect(),
+        None => online_cpus().into_iter().map(|c| (-1, c)).collect(),
+    };
+    let events: Vec<Event> = targets
+        .into_iter()
+        .map(|(pid, cpu)| {
+            Event::with_attr(options.event.clone(), &mut attr.clone(), Some(pid), cpu)
+                .unwrap_or_else(|e| {
+                    eprintln!("ruperf top: {}", e);
+                    std::process::exit(1);
+                })
+        })
+        .collect();
+    let mut rings: Vec<RingBuffer> = Vec::new();
+    for event in &events {
+        let ring = event.fd.mmap(options.mmap_pages).unwrap_or_else(|e| {
+            eprintln!("ruperf top: {}, try fewer --mmap-pages", e);
+            std::process::exit(1);
+        });
+        rings.push(ring);
+        if let Err(e) = event.fd.enable() {
+            eprintln!("ruperf top: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let target = match options.pid {
+        Some(pid) => format!("pid {}", pid),
+        None => "all CPUs".to_string(),
+    };
+
+    let mut symbolizer = Symbolizer::new();
+    let mut comms: HashMap<u32, String> = HashMap::new();
+    let mut state = TopState {
+        histogram: HashMap::new(),
+        samples: 0,
+        sort: TopSort::Overhead,
+        pid_filter: None,
+        comm_filter: None,
+        frozen: false,
+        prompt: None,
+        quit: false,
+    };
+    let terminal = RawTerminal::new();
+    let delay = Duration::from_secs(options.delay.max(1));
+    let mut last_draw = Instant::now();
+
+    while !state.quit {
+        if let Err(e) = ring::poll(&rings, POLL_TIMEOUT_MS) {
+            drop(terminal);
+            eprintln!("ruperf top: {}", e);
+            std::process::exit(1);
+        }
+        for ring in rings.iter_mut() {
+            for raw in ring.drain() {
+                match parse_record(&raw, attr.sample_type, attr.read_format) {
+                    Some(RawRecord::Sample(s)) => {
+                        // The idle task is sampled as pid 0.
+                        if s.pid == 0 {
+                            continue;
+                        }
+                        let kernel = s.misc as u32 & PERF_RECORD_MISC_CPUMODE_MASK
+                            == PERF_RECORD_MISC_KERNEL;
+                        if !kernel && !symbolizer.has_maps(s.pid) {
+                            symbolizer
+                                .add_snapshot(s.pid, read_maps(s.pid as i32).unwrap_or_default());
+                        }
+                        let comm = comms
+                            .entry(s.pid)
+                            .or_insert_with(|| read_comm(s.pid))
+                            .clone();
+                        let loc = symbolizer.resolve(s.pid, s.ip, kernel);
+                        let key = TopKey {
+                            pid: s.pid,
+                            comm,
+                            dso: loc.dso_name().to_string(),
+                            sym: format!(
+                                "[{}] {}",
+                                if kernel { 'k' } else { '.' },
+                                loc.symbol_name()
+                            ),
+                        };
+                        *state.histogram.entry(key).or_insert(0.0) += s.period as f64;
+                        state.samples += 1;
+                    }
+                    Some(RawRecord::Comm {
+                        pid, comm, exec, ..
+                    }) => {
+                        if exec {
+                            symbolizer.forget(pid);
+                        }
+                        comms.insert(pid, comm);
+                    }
+                    Some(RawRecord::Mmap {
+                        pid,
+                        addr,
+                        len,
+                        pgoff,
+                        filename,
+                        ..
+                    }) if symbolizer.has_maps(pid) => symbolizer.add_map(
+                        pid,
+                        MapEntry {
+                            start: addr,
+                            end: addr + len,
+                            pgoff,
+                            path: filename,
+                        },
+                    ),
+                    _ => {}
+                }
+            }
+        }
+        for byte in terminal.read_keys() {
+            state.key(byte);
+        }
+        let elapsed = last_draw.elapsed();
+        if elapsed >= delay {
+            // The terminal is gone if it can't be written to.
+            if !state.frozen && draw(&state, &options.event, &target, elapsed).is_err() {
+                break;
+            }
+            state.decay();
+            state.samples = 0;
+            last_draw = Instant::now();
+        }
+        if let Some(pid) = options.pid {
+            if fs::metadata(format!("/proc/{}", pid)).is_err() {
+                break;
+            }
+        }
+    }
+    drop(terminal);
+    let _ = writeln!(io::stdout());
+}
+
+#[cfg(test)]
+#[test]
+fn top_keys_test() {
+    let mut state = TopState {
+        histogram: HashMap::new(),
+        samples: 0,
+        sort: TopSort::Overhead,
+        pid_filter: None,
+        comm_filter: None,
+        frozen: false,
+        prompt: None,
+        quit: false,
+    };
+    for b in b"p42\rcpy\x7fython\rfs" {
+        state.key(*b);
+    }
+    assert_eq!(state.pid_filter, Some(42));
+    assert_eq!(state.comm_filter.as_deref(), Some("python"));
+    assert!(state.frozen);
+    assert_eq!(state.sort, TopSort::Pid);
+    let key = TopKey {
+        pid: 42,
+        comm: "python3".to_string(),
+        dso: "python3".to_string(),
+        sym: "[.] main".to_string(),
+    };
+    assert!(state.matches(&key));
+    state.histogram.insert(key, 100.0);
+    state.decay();
+    assert_eq!(state.histogram.values().next(), Some(&87.5));
+    state.key(b'q');
+    assert!(state.quit);
+}
@@ -1,4 +1,4 @@
-//! Errors for `ruperf stat`.
+//! Errors for `ruperf` subcommands.
 use thiserror::Error;
 
 /// Parse errors for CLI
@@ -6,4 +6,43 @@ use thiserror::Error;
 pub enum ParseError {
     #[error("Invalid Event")]
     InvalidEvent,
+    #[error("Unknown PMU '{0}'")]
+    UnknownPmu(String),
+    #[error("Invalid PMU term '{0}'")]
+    InvalidPmuTerm(String),
+    #[error("Unknown tracepoint '{0}', is tracefs mounted?")]
+    UnknownTracepoint(String),
+    #[error("Invalid event modifiers ':{0}', {1}")]
+    InvalidModifiers(String, &'static str),
+    #[error("Invalid event group, expected {{event,event,..}}")]
+    InvalidGroup,
+    #[error("Invalid CPU list '{0}', expected online CPUs such as 0,2-4")]
+    InvalidCpuList(String),
+    #[error("Invalid interval, expected at least 10 milliseconds")]
+    InvalidInterval,
+    #[error("Invalid repeat count, expected at least 1")]
+    InvalidRepeat,
+    #[error("Invalid metric '{0}'")]
+    InvalidMetric(String),
+    #[error("Can't read metric file '{0}'")]
+    MetricFile(String),
+    #[error("No such process or thread '{0}'")]
+    NoSuchTask(String),
+    #[error("Invalid sort key, expected one of comm, dso, sym")]
+    UnknownSortKey,
+    #[error("Invalid call graph order, expected caller or callee")]
+    UnknownCallGraph,
+    #[error("Invalid size, expected a number with an optional K, M or G suffix")]
+    InvalidSize,
+}
+
+/// Errors reading or writing a `ruperf record` data file.
+#[derive(Error, Debug)]
+pub enum DataError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Malformed record: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Not a ruperf data file, or an unsupported version")]
+    BadHeader,
 }